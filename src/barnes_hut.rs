@@ -0,0 +1,279 @@
+use bevy_math::DVec3;
+
+use crate::gravity::Gravity;
+
+/// Nodes this deep keep all their bodies in a single leaf, so that bodies sharing a position
+/// can't make the tree grow forever
+const MAX_DEPTH: usize = 64;
+
+/// Octree over the bodies. Every node keeps the total mass and the center of mass of the
+/// bodies inside of it, so that a far away group of bodies can be treated as a single body
+pub struct Octree {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    center: DVec3,
+    half_size: f64,
+    mass: f64,
+    /// Sum of `mass * position` of the bodies inside. Divided by `mass` gives the center of mass
+    mass_moment: DVec3,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    Empty,
+    Leaf(Vec<usize>),
+    /// Index of the first of 8 consecutive children
+    Internal(usize),
+}
+
+impl Node {
+    fn new(center: DVec3, half_size: f64) -> Self {
+        Self {
+            center,
+            half_size,
+            mass: 0.0,
+            mass_moment: DVec3::ZERO,
+            kind: NodeKind::Empty,
+        }
+    }
+
+    fn center_of_mass(&self) -> DVec3 {
+        self.mass_moment / self.mass
+    }
+
+    fn contains(&self, position: DVec3) -> bool {
+        (position - self.center).abs().max_element() <= self.half_size
+    }
+
+    fn octant(&self, position: DVec3) -> usize {
+        (position.x >= self.center.x) as usize
+            | ((position.y >= self.center.y) as usize) << 1
+            | ((position.z >= self.center.z) as usize) << 2
+    }
+
+    fn child_center(&self, octant: usize) -> DVec3 {
+        let quarter = self.half_size / 2.0;
+        let offset = |bit: usize| if octant & bit == 0 { -quarter } else { quarter };
+
+        self.center + DVec3::new(offset(1), offset(2), offset(4))
+    }
+}
+
+impl Octree {
+    pub fn new(positions: &[DVec3], masses: &[f64]) -> Self {
+        let (min, max) = positions.iter().fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(min, max), position| (min.min(*position), max.max(*position)),
+        );
+        let center = (min + max) / 2.0;
+        let half_size = ((max - min).max_element() / 2.0).max(f64::MIN_POSITIVE);
+
+        let mut tree = Self {
+            nodes: vec![Node::new(center, half_size)],
+        };
+
+        for body in 0..positions.len() {
+            tree.insert(0, body, positions, masses, 0);
+        }
+
+        tree
+    }
+
+    fn insert(
+        &mut self,
+        node_index: usize,
+        body: usize,
+        positions: &[DVec3],
+        masses: &[f64],
+        depth: usize,
+    ) {
+        let node = &mut self.nodes[node_index];
+        node.mass += masses[body];
+        node.mass_moment += positions[body] * masses[body];
+
+        match &mut node.kind {
+            NodeKind::Empty => node.kind = NodeKind::Leaf(vec![body]),
+            NodeKind::Leaf(bodies) if depth >= MAX_DEPTH => bodies.push(body),
+            NodeKind::Leaf(bodies) => {
+                let mut bodies = std::mem::take(bodies);
+                bodies.push(body);
+
+                let first_child = self.split(node_index);
+
+                for body in bodies {
+                    let child = first_child + self.nodes[node_index].octant(positions[body]);
+                    self.insert(child, body, positions, masses, depth + 1);
+                }
+            }
+            NodeKind::Internal(first_child) => {
+                let child = *first_child + node.octant(positions[body]);
+                self.insert(child, body, positions, masses, depth + 1);
+            }
+        }
+    }
+
+    /// Turns a node into an internal one with 8 empty children.
+    /// Returns the index of the first child
+    fn split(&mut self, node_index: usize) -> usize {
+        let first_child = self.nodes.len();
+        let node = &self.nodes[node_index];
+        let children: Vec<Node> = (0..8)
+            .map(|octant| Node::new(node.child_center(octant), node.half_size / 2.0))
+            .collect();
+
+        self.nodes.extend(children);
+        self.nodes[node_index].kind = NodeKind::Internal(first_child);
+
+        first_child
+    }
+
+    /// Acceleration of the body with index `body`. A node is treated as a single body when its
+    /// size seen from the body is smaller than `opening_angle` (size / distance < θ).
+    /// Relativistic corrections are only applied to the bodies that aren't grouped
+    pub fn acceleration(
+        &self,
+        gravity: &Gravity,
+        body: usize,
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+        opening_angle: f64,
+    ) -> DVec3 {
+        let position = positions[body];
+        let mut acceleration = DVec3::ZERO;
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+
+            match &node.kind {
+                NodeKind::Empty => {}
+                NodeKind::Leaf(bodies) => {
+                    for &other in bodies.iter().filter(|other| **other != body) {
+                        acceleration +=
+                            gravity.pair_acceleration(body, other, positions, velocities, masses);
+                    }
+                }
+                NodeKind::Internal(first_child) => {
+                    let center_of_mass = node.center_of_mass();
+                    let distance = position.distance(center_of_mass);
+
+                    if !node.contains(position) && 2.0 * node.half_size < opening_angle * distance {
+                        acceleration += gravity.pull(position, center_of_mass, node.mass);
+                    } else {
+                        stack.extend(*first_child..*first_child + 8);
+                    }
+                }
+            }
+        }
+
+        acceleration
+    }
+}
+
+/// Barnes–Hut accelerations of every body computed on the current thread,
+/// indexed the same way as `positions` and `masses`
+pub fn accelerations(
+    gravity: &Gravity,
+    positions: &[DVec3],
+    velocities: &[DVec3],
+    masses: &[f64],
+    opening_angle: f64,
+) -> Vec<DVec3> {
+    if positions.is_empty() {
+        return vec![];
+    }
+
+    let tree = Octree::new(positions, masses);
+
+    (0..positions.len())
+        .map(|body| tree.acceleration(gravity, body, positions, velocities, masses, opening_angle))
+        .collect()
+}
+
+/// Relative error of the Barnes–Hut accelerations compared to the direct summation
+#[derive(Debug)]
+pub struct ForceError {
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Computes the accelerations with both solvers and compares them body by body
+pub fn force_error(
+    gravity: &Gravity,
+    positions: &[DVec3],
+    velocities: &[DVec3],
+    masses: &[f64],
+    opening_angle: f64,
+) -> ForceError {
+    let errors: Vec<f64> = accelerations(gravity, positions, velocities, masses, opening_angle)
+        .into_iter()
+        .zip(gravity.direct_accelerations(positions, velocities, masses))
+        .map(|(approximate, exact)| {
+            let magnitude = exact.length();
+
+            if magnitude > 0.0 {
+                (approximate - exact).length() / magnitude
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    ForceError {
+        max: errors.iter().copied().fold(0.0, f64::max),
+        mean: errors.iter().sum::<f64>() / errors.len().max(1) as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bodies spread over a cube 10¹¹ m wide, more than a task handles
+    fn bodies() -> (Vec<DVec3>, Vec<DVec3>, Vec<f64>) {
+        let spread = |body: usize, step: f64| (body as f64 * step).fract() - 0.5;
+        let positions: Vec<DVec3> = (0..200)
+            .map(|body| {
+                DVec3::new(
+                    spread(body, 0.618),
+                    spread(body, 0.414),
+                    spread(body, 0.732),
+                )
+            })
+            .map(|position| position * 1e11)
+            .collect();
+        let velocities = positions
+            .iter()
+            .map(|position| position.cross(DVec3::Z) * 3e-7)
+            .collect();
+        let masses = (0..200)
+            .map(|body| 1e24 * (1.0 + spread(body, 0.236)))
+            .collect();
+
+        (positions, velocities, masses)
+    }
+
+    #[test]
+    fn opening_no_node_is_exact() {
+        let (positions, velocities, masses) = bodies();
+        let error = force_error(&Gravity::default(), &positions, &velocities, &masses, 0.0);
+
+        // only the order of the sums differs
+        assert!(error.max < 1e-14, "{:?}", error);
+    }
+
+    #[test]
+    fn error_grows_with_the_opening_angle() {
+        let (positions, velocities, masses) = bodies();
+        let gravity = Gravity::default();
+
+        let narrow = force_error(&gravity, &positions, &velocities, &masses, 0.3);
+        let wide = force_error(&gravity, &positions, &velocities, &masses, 1.0);
+
+        assert!(narrow.mean < wide.mean, "{:?} {:?}", narrow, wide);
+        assert!(wide.mean < 0.1, "{:?}", wide);
+    }
+}
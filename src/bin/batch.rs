@@ -0,0 +1,415 @@
+//! Integrates a simulation without a window and writes the body states as it goes
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    process,
+};
+
+use bevy_tasks::TaskPool;
+use planetary_simulation_rs::{
+    clock::parse_time,
+    conservation::{ConservationMonitor, DiagnosticsWriter, DEFAULT_DRIFT_THRESHOLD},
+    gravity::ForceSolver,
+    integrator::Integrator,
+    momentum::BarycentricFrame,
+    orbit::{self, OrbitPrimary, SECONDS_PER_DAY},
+    precession::ApsidalPrecession,
+    scenario::Scenario,
+    simulation::Simulation,
+    snapshot::Snapshot,
+    trajectory::{TrajectoryFormat, TrajectoryRecorder},
+};
+
+const USAGE: &str = "\
+Usage: planetary-batch --duration <time> [options]
+
+Options:
+    --scenario <file>       .ron, .toml or .json scenario [default: the solar system]
+    --restore <file>        continue from a snapshot instead of starting a scenario
+    --save <file>           write a snapshot of the end of the run
+    --recenter              move the center of mass to the origin and stop it there before the run
+    --duration <time>       simulated time to integrate over
+    --step <time>           size of a step [default: from the scenario]
+    --every <time>          simulated time between two outputs [default: 1d]
+    --integrator <name>     euler, leapfrog, verlet or dormand-prince [default: from the scenario]
+    --tolerance <number>    error tolerance of dormand-prince [default: 1e-10]
+    --solver <name>         direct or barnes-hut [default: from the scenario]
+    --opening-angle <number>
+                            opening angle of barnes-hut, in radians [default: 0.5]
+    --post-newtonian        add the relativistic correction [default: from the scenario]
+    --format <format>       csv or ndjson [default: csv]
+    --elements-around <body>
+                            add the osculating orbital elements around this body to every row
+    --output <file>         file to write to [default: stdout]
+    --diagnostics <file>    write the energy, momentum and angular momentum with their drift
+                            into a .csv or .ndjson file at every output
+    --drift-threshold <number>
+                            relative drift of a conserved quantity that is warned about [default: 1e-6]
+    --precession-of <body>  measure the periapsis precession of this body at every output
+
+Times are in seconds, or with a unit: 30m, 12h, 10d, 2y";
+
+const DEFAULT_TOLERANCE: f64 = 1e-10;
+const DEFAULT_OPENING_ANGLE: f64 = 0.5;
+
+struct Options {
+    scenario: Option<String>,
+    restore: Option<String>,
+    save: Option<String>,
+    recenter: bool,
+    duration: f64,
+    step: Option<f64>,
+    every: f64,
+    integrator: Option<Integrator>,
+    solver: Option<ForceSolver>,
+    post_newtonian: bool,
+    format: TrajectoryFormat,
+    elements_around: Option<String>,
+    output: Option<String>,
+    diagnostics: Option<String>,
+    drift_threshold: f64,
+    precession_of: Option<String>,
+}
+
+fn main() {
+    let options = match parse_options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let exit_on_error = |path: &str, error| -> ! {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    };
+
+    let (scenario, mut simulation) = match (&options.restore, &options.scenario) {
+        (Some(path), _) => {
+            let snapshot = Snapshot::load(path).unwrap_or_else(|error| exit_on_error(path, error));
+
+            (snapshot.scenario.clone(), snapshot.to_simulation())
+        }
+        (None, path) => {
+            let scenario = match path {
+                Some(path) => {
+                    Scenario::load(path).unwrap_or_else(|error| exit_on_error(path, error))
+                }
+                None => Scenario::solar_system(),
+            };
+            let simulation = scenario.to_simulation();
+
+            (scenario, simulation)
+        }
+    };
+
+    if options.recenter {
+        let (position_shift, velocity_shift) =
+            BarycentricFrame::default().recenter(&mut simulation.bodies, simulation.elapsed);
+        simulation.particles.shift(position_shift, velocity_shift);
+    }
+
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(error) => {
+                eprintln!("Can't create {}: {}", path, error);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let step_size = options.step.unwrap_or(scenario.time_step);
+
+    let mut recorder =
+        TrajectoryRecorder::new(BufWriter::new(output), options.format, options.every);
+    recorder.elements_around = options.elements_around.clone();
+
+    let mut monitor = ConservationMonitor::new(options.drift_threshold);
+    let mut diagnostics = options.diagnostics.as_ref().map(|path| {
+        DiagnosticsWriter::create(path).unwrap_or_else(|error| {
+            eprintln!("Can't create {}: {}", path, error);
+            process::exit(1);
+        })
+    });
+
+    let mut precession = ApsidalPrecession::default();
+
+    let simulation = match run(
+        simulation,
+        &options,
+        step_size,
+        &mut recorder,
+        &mut monitor,
+        diagnostics.as_mut(),
+        &mut precession,
+    ) {
+        Ok(simulation) => simulation,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    if let Some(sample) = monitor.latest() {
+        eprintln!(
+            "Drift after {} s: energy {:.3e}, momentum {:.3e}, angular momentum {:.3e}",
+            sample.time, sample.energy_drift, sample.momentum_drift, sample.angular_momentum_drift
+        );
+    }
+
+    if let Some(name) = &options.precession_of {
+        match precession.arcseconds_per_century() {
+            Some(rate) => eprintln!(
+                "{} periapsis precession: {:.2}″ per century ({} samples)",
+                name,
+                rate,
+                precession.samples().len()
+            ),
+            None => eprintln!(
+                "The periapsis precession of {} needs two samples of its orbit to be measured",
+                name
+            ),
+        }
+    }
+
+    for plan in &simulation.flight_plans {
+        eprintln!(
+            "{} used {:.3} of {:.3} m/s of delta-v",
+            plan.spacecraft,
+            plan.used_delta_v(),
+            plan.planned_delta_v()
+        );
+    }
+
+    if let Some(path) = &options.save {
+        if let Err(error) = Snapshot::of_simulation(&simulation, step_size, &scenario).save(path) {
+            exit_on_error(path, error);
+        }
+    }
+}
+
+/// Integrates `options.duration` seconds on from where the simulation is.
+/// The conserved quantities and the precession are sampled whenever the bodies are recorded
+fn run(
+    mut simulation: Simulation,
+    options: &Options,
+    step_size: f64,
+    recorder: &mut TrajectoryRecorder<impl Write>,
+    monitor: &mut ConservationMonitor,
+    mut diagnostics: Option<&mut DiagnosticsWriter<impl Write>>,
+    precession: &mut ApsidalPrecession,
+) -> io::Result<Simulation> {
+    let pool = TaskPool::new();
+
+    if let Some(integrator) = options.integrator {
+        simulation.integrator = integrator;
+    }
+    if let Some(solver) = options.solver {
+        simulation.gravity.solver = solver;
+    }
+    if options.post_newtonian {
+        simulation.gravity.post_newtonian = true;
+    }
+
+    let end = simulation.elapsed + options.duration;
+    let steps = (options.duration / step_size).ceil() as u64;
+
+    for step in 0..=steps {
+        if recorder.record(simulation.elapsed, &simulation.bodies)? {
+            for quantity in
+                monitor.record(simulation.elapsed, &simulation.bodies, &simulation.gravity)
+            {
+                eprintln!(
+                    "The {} drifted by more than {:e} after {} s",
+                    quantity, monitor.threshold, simulation.elapsed
+                );
+            }
+
+            if let (Some(diagnostics), Some(sample)) = (&mut diagnostics, monitor.latest()) {
+                diagnostics.write(sample)?;
+            }
+
+            if let Some(name) = &options.precession_of {
+                record_precession(precession, name, &simulation);
+            }
+        }
+
+        if step == steps {
+            break;
+        }
+
+        let dt = step_size.min(end - simulation.elapsed);
+
+        let outcome = simulation.step(&pool, dt).map_err(|diverged| {
+            io::Error::other(format!(
+                "State of {} is not finite anymore after {} s",
+                diverged.names.join(", "),
+                simulation.elapsed
+            ))
+        })?;
+
+        for burn in outcome.burns {
+            eprintln!(
+                "{} burned {:.3} m/s at {} s",
+                burn.spacecraft,
+                burn.velocity_change.length(),
+                burn.time
+            );
+        }
+
+        for collision in simulation.merge_collisions() {
+            eprintln!(
+                "{} collided with {} at {} s",
+                collision.first, collision.second, simulation.elapsed
+            );
+            // merging loses energy, the drift is measured from after the collision
+            monitor.rebase();
+        }
+    }
+
+    Ok(simulation)
+}
+
+/// Adds a sample of the orbit of the body named `name` around its strongest primary
+fn record_precession(precession: &mut ApsidalPrecession, name: &str, simulation: &Simulation) {
+    let primaries = vec![OrbitPrimary::Strongest; simulation.bodies.len()];
+    let references = orbit::references(&simulation.bodies, &primaries);
+
+    if let Some((body, Some(reference))) = simulation
+        .bodies
+        .iter()
+        .zip(references)
+        .find(|(body, _)| body.name == name)
+    {
+        precession.record(
+            simulation.elapsed,
+            body.position - reference.position,
+            body.velocity - reference.velocity,
+            reference.sgp,
+        );
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut scenario = None;
+    let mut restore = None;
+    let mut save = None;
+    let mut recenter = false;
+    let mut duration = None;
+    let mut step = None;
+    let mut every = SECONDS_PER_DAY;
+    let mut integrator = None;
+    let mut tolerance = DEFAULT_TOLERANCE;
+    let mut solver = None;
+    let mut opening_angle = DEFAULT_OPENING_ANGLE;
+    let mut post_newtonian = false;
+    let mut format = TrajectoryFormat::Csv;
+    let mut elements_around = None;
+    let mut output = None;
+    let mut diagnostics = None;
+    let mut drift_threshold = DEFAULT_DRIFT_THRESHOLD;
+    let mut precession_of = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value of {}", arg))
+        };
+
+        match arg.as_str() {
+            "--scenario" => scenario = Some(value()?),
+            "--restore" => restore = Some(value()?),
+            "--save" => save = Some(value()?),
+            "--recenter" => recenter = true,
+            "--duration" => duration = Some(parse_time(&value()?)?),
+            "--step" => step = Some(parse_time(&value()?)?),
+            "--every" => every = parse_time(&value()?)?,
+            "--integrator" => integrator = Some(value()?),
+            "--tolerance" => {
+                let value = value()?;
+                tolerance = value
+                    .parse()
+                    .map_err(|_| format!("Invalid tolerance: {}", value))?;
+            }
+            "--solver" => solver = Some(value()?),
+            "--opening-angle" => {
+                let value = value()?;
+                opening_angle = value
+                    .parse()
+                    .map_err(|_| format!("Invalid opening angle: {}", value))?;
+            }
+            "--post-newtonian" => post_newtonian = true,
+            "--format" => {
+                format = match value()?.as_str() {
+                    "csv" => TrajectoryFormat::Csv,
+                    "ndjson" => TrajectoryFormat::Ndjson,
+                    other => return Err(format!("Unknown format: {}", other)),
+                }
+            }
+            "--elements-around" => elements_around = Some(value()?),
+            "--output" => output = Some(value()?),
+            "--diagnostics" => diagnostics = Some(value()?),
+            "--drift-threshold" => {
+                let value = value()?;
+                drift_threshold = value
+                    .parse()
+                    .map_err(|_| format!("Invalid drift threshold: {}", value))?;
+            }
+            "--precession-of" => precession_of = Some(value()?),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+
+    let integrator = match integrator.as_deref() {
+        None => None,
+        Some("euler") => Some(Integrator::Euler),
+        Some("leapfrog") => Some(Integrator::Leapfrog),
+        Some("verlet") => Some(Integrator::VelocityVerlet),
+        Some("dormand-prince") => Some(Integrator::dormand_prince(tolerance)),
+        Some(other) => return Err(format!("Unknown integrator: {}", other)),
+    };
+
+    let solver = match solver.as_deref() {
+        None => None,
+        Some("direct") => Some(ForceSolver::Direct),
+        Some("barnes-hut") => Some(ForceSolver::BarnesHut { opening_angle }),
+        Some(other) => return Err(format!("Unknown solver: {}", other)),
+    };
+
+    let duration = duration.ok_or("--duration is required")?;
+
+    if step.unwrap_or(1.0) <= 0.0 || every <= 0.0 || duration < 0.0 {
+        return Err("Times must be positive".to_string());
+    }
+    if opening_angle <= 0.0 || !opening_angle.is_finite() {
+        return Err("The opening angle must be positive".to_string());
+    }
+
+    Ok(Options {
+        scenario,
+        restore,
+        save,
+        recenter,
+        duration,
+        step,
+        every,
+        integrator,
+        solver,
+        post_newtonian,
+        format,
+        elements_around,
+        output,
+        diagnostics,
+        drift_threshold,
+        precession_of,
+    })
+}
@@ -0,0 +1,212 @@
+/// Keeps track of the simulated time and decides how many physics steps to take every frame
+#[derive(Debug)]
+pub struct SimulationClock {
+    /// Simulated time since the start, in seconds
+    pub elapsed: f64,
+    /// Size of a single physics step, in simulated seconds
+    pub step: f64,
+    /// Simulated seconds per real second. Negative values integrate backwards
+    pub warp: f64,
+    pub paused: bool,
+    /// Upper bound of the steps taken in a frame. When a slow frame asks for more,
+    /// the rest is dropped instead of being caught up on the next frames
+    pub max_steps_per_frame: u32,
+    /// Simulated time not yet covered by a step
+    accumulator: f64,
+    pending_steps: u32,
+}
+
+const WARP_CHANGE_BY: f64 = 2.0;
+
+impl SimulationClock {
+    pub fn new(step: f64, warp: f64) -> Self {
+        Self {
+            elapsed: 0.0,
+            step,
+            warp,
+            paused: false,
+            max_steps_per_frame: 64,
+            accumulator: 0.0,
+            pending_steps: 0,
+        }
+    }
+
+    /// Schedules the steps due after `real_seconds` of real time passed
+    pub fn tick(&mut self, real_seconds: f64) {
+        if self.paused {
+            return;
+        }
+
+        self.accumulator += real_seconds * self.warp.abs();
+
+        let due_steps = (self.accumulator / self.step).floor();
+        let free_slots = self.max_steps_per_frame.saturating_sub(self.pending_steps);
+
+        if due_steps > free_slots as f64 {
+            self.pending_steps += free_slots;
+            self.accumulator = 0.0;
+        } else {
+            self.pending_steps += due_steps as u32;
+            self.accumulator -= due_steps * self.step;
+        }
+    }
+
+    /// Takes one of the scheduled steps. Returns `false` when there are none left
+    pub fn take_step(&mut self) -> bool {
+        if self.pending_steps == 0 {
+            return false;
+        }
+
+        self.pending_steps -= 1;
+
+        true
+    }
+
+    /// Signed size of the next step: negative when going backwards in time
+    pub fn step_size(&self) -> f64 {
+        if self.warp < 0.0 {
+            -self.step
+        } else {
+            self.step
+        }
+    }
+
+    /// Records that a step of `dt` seconds was taken
+    pub fn advance(&mut self, dt: f64) {
+        self.elapsed += dt;
+    }
+
+    /// Schedules exactly one step, also when paused
+    pub fn single_step(&mut self) {
+        self.pending_steps += 1;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.pending_steps = 0;
+        self.accumulator = 0.0;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume();
+        } else {
+            self.pause();
+        }
+    }
+
+    pub fn speed_up(&mut self) {
+        self.warp *= WARP_CHANGE_BY;
+    }
+
+    pub fn slow_down(&mut self) {
+        self.warp /= WARP_CHANGE_BY;
+    }
+
+    pub fn reverse(&mut self) {
+        self.warp = -self.warp;
+    }
+}
+
+/// Seconds in a time like `3600`, `90m`, `12h`, `10d` or `2y`
+pub fn parse_time(time: &str) -> Result<f64, String> {
+    let (number, unit) = match time.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => time.split_at(index),
+        _ => (time, "s"),
+    };
+
+    let seconds_per_unit = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        "y" => 365.25 * 86400.0,
+        _ => return Err(format!("Unknown time unit in {}", time)),
+    };
+
+    number
+        .parse::<f64>()
+        .map(|number| number * seconds_per_unit)
+        .map_err(|_| format!("Invalid time: {}", time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take_all(clock: &mut SimulationClock) -> u32 {
+        let mut steps = 0;
+
+        while clock.take_step() {
+            clock.advance(clock.step_size());
+            steps += 1;
+        }
+
+        steps
+    }
+
+    #[test]
+    fn schedules_the_steps_of_the_elapsed_time() {
+        let mut clock = SimulationClock::new(10.0, 1000.0);
+
+        clock.tick(0.025);
+        assert_eq!(take_all(&mut clock), 2);
+        // the 5 seconds left over are caught up on the next frame
+        clock.tick(0.005);
+        assert_eq!(take_all(&mut clock), 1);
+        assert_eq!(clock.elapsed, 30.0);
+    }
+
+    #[test]
+    fn slow_frames_take_at_most_the_bound_of_steps() {
+        let mut clock = SimulationClock::new(10.0, 1000.0);
+
+        clock.tick(10.0);
+        assert_eq!(take_all(&mut clock), clock.max_steps_per_frame);
+        // the rest was dropped
+        clock.tick(0.0);
+        assert_eq!(take_all(&mut clock), 0);
+    }
+
+    #[test]
+    fn paused_clocks_only_take_single_steps() {
+        let mut clock = SimulationClock::new(10.0, 1000.0);
+
+        clock.tick(0.005);
+        clock.pause();
+        clock.tick(1.0);
+        assert_eq!(take_all(&mut clock), 0);
+
+        clock.single_step();
+        assert_eq!(take_all(&mut clock), 1);
+        assert_eq!(clock.elapsed, 10.0);
+
+        // the time before the pause isn't caught up
+        clock.resume();
+        clock.tick(0.005);
+        assert_eq!(take_all(&mut clock), 0);
+    }
+
+    #[test]
+    fn reversed_clocks_step_backwards() {
+        let mut clock = SimulationClock::new(10.0, 1000.0);
+
+        clock.reverse();
+        clock.tick(0.02);
+        assert_eq!(take_all(&mut clock), 2);
+        assert_eq!(clock.elapsed, -20.0);
+    }
+
+    #[test]
+    fn parses_times_with_units() {
+        assert_eq!(parse_time("3600"), Ok(3600.0));
+        assert_eq!(parse_time("90m"), Ok(5400.0));
+        assert_eq!(parse_time("2d"), Ok(172800.0));
+        assert!(parse_time("2w").is_err());
+        assert!(parse_time("h").is_err());
+    }
+}
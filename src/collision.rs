@@ -0,0 +1,129 @@
+use bevy_math::DVec3;
+
+/// Physical state of a body taking part in a collision
+#[derive(Clone, Copy, Debug)]
+pub struct BodyState {
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub mass: f64,
+    pub diameter: f64,
+}
+
+/// Pairs of bodies whose spheres touch or overlap. The lower index goes first in every pair,
+/// pairs are sorted so the result doesn't depend on the order the bodies were checked in.
+///
+/// Bodies are swept along the X axis, only the ones whose extents overlap on it are compared
+pub fn find_collisions(positions: &[DVec3], radii: &[f64]) -> Vec<(usize, usize)> {
+    let left_edge = |body: usize| positions[body].x - radii[body];
+
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|first, second| left_edge(*first).total_cmp(&left_edge(*second)));
+
+    let mut collisions = vec![];
+
+    for (sorted_index, &first) in order.iter().enumerate() {
+        let right_edge = positions[first].x + radii[first];
+
+        for &second in order[sorted_index + 1..]
+            .iter()
+            .take_while(|second| left_edge(**second) <= right_edge)
+        {
+            let touching_distance = radii[first] + radii[second];
+
+            if positions[first].distance_squared(positions[second]) <= touching_distance.powi(2) {
+                collisions.push((first.min(second), first.max(second)));
+            }
+        }
+    }
+
+    collisions.sort_unstable();
+
+    collisions
+}
+
+/// Perfectly inelastic merge: mass and momentum are conserved, the merged body is placed
+/// at the center of mass and keeps the total volume of both bodies.
+/// Two massless bodies have no center of mass, they're averaged with equal weights
+pub fn merge(first: BodyState, second: BodyState) -> BodyState {
+    let mass = first.mass + second.mass;
+    let average = |of_first: DVec3, of_second: DVec3| {
+        if mass > 0.0 {
+            (of_first * first.mass + of_second * second.mass) / mass
+        } else {
+            (of_first + of_second) / 2.0
+        }
+    };
+
+    BodyState {
+        position: average(first.position, second.position),
+        velocity: average(first.velocity, second.velocity),
+        mass,
+        diameter: (first.diameter.powi(3) + second.diameter.powi(3)).cbrt(),
+    }
+}
+
+/// Merges every pair of colliding bodies. The heavier body of a pair survives and takes
+/// the merged state, the lighter one is merged away and doesn't take part in later pairs.
+/// Massless bodies, like spacecraft flying together, pass through each other.
+///
+/// Returns `(survivor, merged away)` index pairs in the order they were merged
+pub fn merge_collisions(bodies: &mut [BodyState]) -> Vec<(usize, usize)> {
+    let positions: Vec<DVec3> = bodies.iter().map(|body| body.position).collect();
+    let radii: Vec<f64> = bodies.iter().map(|body| body.diameter / 2.0).collect();
+
+    let mut merged_away = vec![false; bodies.len()];
+    let mut merges = vec![];
+
+    for (first, second) in find_collisions(&positions, &radii) {
+        if merged_away[first] || merged_away[second] {
+            continue;
+        }
+        if bodies[first].mass == 0.0 && bodies[second].mass == 0.0 {
+            continue;
+        }
+
+        let (survivor, removed) = if bodies[first].mass >= bodies[second].mass {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        bodies[survivor] = merge(bodies[survivor], bodies[removed]);
+        merged_away[removed] = true;
+        merges.push((survivor, removed));
+    }
+
+    merges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let first = BodyState {
+            position: DVec3::new(1.0e9, -2.0e8, 3.0e7),
+            velocity: DVec3::new(3.0e4, 1.0e3, -2.0e2),
+            mass: 5.972e24,
+            diameter: 12.742e6,
+        };
+        let second = BodyState {
+            position: DVec3::new(1.0e9 + 5.0e6, -2.0e8, 3.0e7),
+            velocity: DVec3::new(-1.0e3, 2.0e4, 5.0e2),
+            mass: 7.342e22,
+            diameter: 3.474e6,
+        };
+
+        let merged = merge(first, second);
+        let momentum = first.velocity * first.mass + second.velocity * second.mass;
+        let center_of_mass = (first.position * first.mass + second.position * second.mass)
+            / (first.mass + second.mass);
+
+        assert_eq!(merged.mass, first.mass + second.mass);
+        assert!((merged.velocity * merged.mass - momentum).length() <= 1e-15 * momentum.length());
+        assert!((merged.position - center_of_mass).length() <= 1e-15 * center_of_mass.length());
+        let volume = first.diameter.powi(3) + second.diameter.powi(3);
+        assert!((merged.diameter.powi(3) / volume - 1.0).abs() < 1e-14);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use bevy_math::DVec3;
+use serde::Serialize;
+
+use crate::{gravity::Gravity, simulation::Body, trajectory::TrajectoryFormat};
+
+/// Relative drift of a quantity past which it's reported, by default
+pub const DEFAULT_DRIFT_THRESHOLD: f64 = 1e-6;
+
+/// Quantities an isolated system keeps, whatever the bodies do
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Quantity {
+    Energy,
+    Momentum,
+    AngularMomentum,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Energy => "energy",
+            Self::Momentum => "momentum",
+            Self::AngularMomentum => "angular momentum",
+        })
+    }
+}
+
+/// Totals of all the bodies at a moment. The angular momentum is around the origin
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Totals {
+    /// Joules
+    pub kinetic_energy: f64,
+    /// Joules
+    pub potential_energy: f64,
+    /// Kilograms times meters per second
+    pub momentum: DVec3,
+    /// Kilograms times square meters per second
+    pub angular_momentum: DVec3,
+    /// Sum of the momentum magnitudes, which the momentum drift is relative to
+    /// because the total momentum is often zero
+    #[serde(skip)]
+    momentum_scale: f64,
+    /// Sum of the angular momentum magnitudes, for the same reason
+    #[serde(skip)]
+    angular_momentum_scale: f64,
+}
+
+impl Totals {
+    /// The potential energy follows the force law of `gravity`, but without the relativistic
+    /// correction, so the energy drifts a little when that's turned on
+    pub fn of(bodies: &[Body], gravity: &Gravity) -> Self {
+        let positions: Vec<DVec3> = bodies.iter().map(|body| body.position).collect();
+        let masses: Vec<f64> = bodies.iter().map(|body| body.mass).collect();
+
+        let mut totals = Self {
+            kinetic_energy: 0.0,
+            potential_energy: gravity.potential_energy(&positions, &masses),
+            momentum: DVec3::ZERO,
+            angular_momentum: DVec3::ZERO,
+            momentum_scale: 0.0,
+            angular_momentum_scale: 0.0,
+        };
+
+        for body in bodies {
+            let momentum = body.velocity * body.mass;
+            let angular_momentum = body.position.cross(momentum);
+
+            totals.kinetic_energy += body.velocity.length_squared() * body.mass / 2.0;
+            totals.momentum += momentum;
+            totals.angular_momentum += angular_momentum;
+            totals.momentum_scale += momentum.length();
+            totals.angular_momentum_scale += angular_momentum.length();
+        }
+
+        totals
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+/// Totals at a moment with how far they drifted from the reference ones
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Sample {
+    /// Simulated seconds
+    pub time: f64,
+    #[serde(flatten)]
+    pub totals: Totals,
+    /// `|E - E₀| / |E₀|`
+    pub energy_drift: f64,
+    /// `|P - P₀|` relative to the sum of the momenta of the bodies at the reference
+    pub momentum_drift: f64,
+    /// `|L - L₀|` relative to the sum of the angular momenta of the bodies at the reference
+    pub angular_momentum_drift: f64,
+}
+
+impl Sample {
+    pub fn drift(&self, quantity: Quantity) -> f64 {
+        match quantity {
+            Quantity::Energy => self.energy_drift,
+            Quantity::Momentum => self.momentum_drift,
+            Quantity::AngularMomentum => self.angular_momentum_drift,
+        }
+    }
+}
+
+/// Samples the conserved quantities of a simulation and tells how far they drifted
+/// from their values at the first sample, which says how much a run can be trusted
+#[derive(Debug)]
+pub struct ConservationMonitor {
+    /// Relative drift past which a quantity is reported
+    pub threshold: f64,
+    reference: Option<Totals>,
+    samples: Vec<Sample>,
+    /// Quantities already reported since the reference was taken
+    reported: Vec<Quantity>,
+}
+
+impl Default for ConservationMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_DRIFT_THRESHOLD)
+    }
+}
+
+impl ConservationMonitor {
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            reference: None,
+            samples: vec![],
+            reported: vec![],
+        }
+    }
+
+    /// Adds a sample of `bodies` at `time`. Returns the quantities that drifted past
+    /// the threshold with it, every quantity is returned once until [`Self::rebase`]
+    pub fn record(&mut self, time: f64, bodies: &[Body], gravity: &Gravity) -> Vec<Quantity> {
+        let totals = Totals::of(bodies, gravity);
+        let reference = *self.reference.get_or_insert(totals);
+
+        let relative = |difference: f64, scale: f64| {
+            if scale > 0.0 {
+                difference / scale
+            } else {
+                difference
+            }
+        };
+
+        let sample = Sample {
+            time,
+            totals,
+            energy_drift: relative(
+                (totals.energy() - reference.energy()).abs(),
+                reference.energy().abs(),
+            ),
+            momentum_drift: relative(
+                (totals.momentum - reference.momentum).length(),
+                reference.momentum_scale,
+            ),
+            angular_momentum_drift: relative(
+                (totals.angular_momentum - reference.angular_momentum).length(),
+                reference.angular_momentum_scale,
+            ),
+        };
+        self.samples.push(sample);
+
+        let drifted: Vec<Quantity> = [
+            Quantity::Energy,
+            Quantity::Momentum,
+            Quantity::AngularMomentum,
+        ]
+        .into_iter()
+        .filter(|quantity| {
+            sample.drift(*quantity) > self.threshold && !self.reported.contains(quantity)
+        })
+        .collect();
+        self.reported.extend(&drifted);
+
+        drifted
+    }
+
+    /// Every sample since the start or the last [`Self::reset`]
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.last()
+    }
+
+    /// Measures the drift from the next sample on, for when the bodies were changed
+    /// on purpose: added, removed or merged
+    pub fn rebase(&mut self) {
+        self.reference = None;
+        self.reported.clear();
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.threshold);
+    }
+}
+
+/// Writes conservation samples into a CSV or NDJSON file as they're taken
+pub struct DiagnosticsWriter<W: Write> {
+    output: W,
+    format: TrajectoryFormat,
+    header_written: bool,
+}
+
+impl DiagnosticsWriter<BufWriter<File>> {
+    /// Writes into a new file, the format is given by its extension
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let format = TrajectoryFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown diagnostics format, expected .csv, .ndjson or .jsonl",
+            )
+        })?;
+
+        Ok(Self::new(BufWriter::new(File::create(path)?), format))
+    }
+}
+
+impl<W: Write> DiagnosticsWriter<W> {
+    pub fn new(output: W, format: TrajectoryFormat) -> Self {
+        Self {
+            output,
+            format,
+            header_written: false,
+        }
+    }
+
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        match self.format {
+            TrajectoryFormat::Csv => {
+                if !self.header_written {
+                    writeln!(
+                        self.output,
+                        "time,kinetic_energy,potential_energy,energy,px,py,pz,lx,ly,lz,\
+                         energy_drift,momentum_drift,angular_momentum_drift"
+                    )?;
+                    self.header_written = true;
+                }
+
+                let totals = &sample.totals;
+                writeln!(
+                    self.output,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    sample.time,
+                    totals.kinetic_energy,
+                    totals.potential_energy,
+                    totals.energy(),
+                    totals.momentum.x,
+                    totals.momentum.y,
+                    totals.momentum.z,
+                    totals.angular_momentum.x,
+                    totals.angular_momentum.y,
+                    totals.angular_momentum.z,
+                    sample.energy_drift,
+                    sample.momentum_drift,
+                    sample.angular_momentum_drift,
+                )?;
+            }
+            TrajectoryFormat::Ndjson => {
+                serde_json::to_writer(&mut self.output, sample)?;
+                writeln!(self.output)?;
+            }
+        }
+
+        self.output.flush()
+    }
+}
@@ -0,0 +1,295 @@
+use bevy_math::DVec3;
+use bevy_tasks::TaskPool;
+use serde::{Deserialize, Serialize};
+
+use crate::barnes_hut::Octree;
+
+pub const G: f64 = 6.67e-11;
+/// Speed of light, m/s
+pub const C: f64 = 299_792_458.0;
+
+/// Bodies are split into chunks of this size, every chunk is handled by a separate task
+const BODIES_PER_TASK: usize = 64;
+
+/// How the gravitational accelerations are computed
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum ForceSolver {
+    /// Sums the pull of every other body, O(N²)
+    #[default]
+    Direct,
+    /// Barnes–Hut octree, O(N log N). Groups of bodies whose size seen from a body is smaller
+    /// than `opening_angle` (in radians) are treated as a single body at their center of mass
+    BarnesHut { opening_angle: f64 },
+}
+
+/// Settings of the gravitational force law. Settings left out of a scenario are the defaults:
+/// direct summation of the Newtonian pull, neither softened nor limited
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Gravity {
+    pub solver: ForceSolver,
+    /// Plummer softening length in meters: the pull is `G * m * r / (r² + ε²)^(3/2)`,
+    /// which stays finite when two bodies get close to each other
+    pub softening: f64,
+    /// When set, the pull between bodies closer than this distance `d` (in meters) grows
+    /// linearly with their distance, `G * m * r / d³`, like inside a uniform sphere of
+    /// radius `d`. It's the largest at `d` and goes to zero when the bodies meet
+    pub min_distance: Option<f64>,
+    /// Adds the first post-Newtonian (1PN) correction of general relativity. Every pair
+    /// is treated as a test body moving around a mass, which holds for a planet and its star
+    /// and makes perihelia advance (by 43″ per century for Mercury)
+    pub post_newtonian: bool,
+}
+
+impl Gravity {
+    /// Gravitational acceleration of every body caused by all the other bodies.
+    /// `positions`, `velocities` and `masses` are indexed the same way, so is the result.
+    ///
+    /// Bodies are spread over the tasks of `pool`, but every acceleration is summed up
+    /// by a single task in the same order, so the result doesn't depend on the number of threads
+    pub fn accelerations(
+        &self,
+        pool: &TaskPool,
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+    ) -> Vec<DVec3> {
+        match self.solver {
+            ForceSolver::Direct => in_parallel(pool, positions.len(), |body| {
+                self.direct_acceleration(body, positions, velocities, masses)
+            }),
+            ForceSolver::BarnesHut { opening_angle } => {
+                let tree = Octree::new(positions, masses);
+
+                in_parallel(pool, positions.len(), |body| {
+                    tree.acceleration(self, body, positions, velocities, masses, opening_angle)
+                })
+            }
+        }
+    }
+
+    /// Accelerations of massless test particles at `points` caused by the bodies at `positions`.
+    /// The particles don't pull each other and the relativistic correction isn't applied to them
+    pub fn test_particle_accelerations(
+        &self,
+        pool: &TaskPool,
+        points: &[DVec3],
+        positions: &[DVec3],
+        masses: &[f64],
+    ) -> Vec<DVec3> {
+        in_parallel(pool, points.len(), |point| {
+            positions
+                .iter()
+                .zip(masses)
+                .fold(DVec3::ZERO, |total, (position, mass)| {
+                    total + self.pull(points[point], *position, *mass)
+                })
+        })
+    }
+
+    /// Accelerations computed by summing over all the pairs of bodies, on the current thread
+    pub fn direct_accelerations(
+        &self,
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+    ) -> Vec<DVec3> {
+        (0..positions.len())
+            .map(|body| self.direct_acceleration(body, positions, velocities, masses))
+            .collect()
+    }
+
+    fn direct_acceleration(
+        &self,
+        body: usize,
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+    ) -> DVec3 {
+        (0..positions.len())
+            .filter(|other| *other != body)
+            .fold(DVec3::ZERO, |total, other| {
+                total + self.pair_acceleration(body, other, positions, velocities, masses)
+            })
+    }
+
+    /// Whether the accelerations depend on the velocities of the bodies too
+    pub fn depends_on_velocities(&self) -> bool {
+        self.post_newtonian
+    }
+
+    /// Acceleration of the body with index `body` caused by the body with index `other`,
+    /// including the relativistic correction when it's turned on
+    pub fn pair_acceleration(
+        &self,
+        body: usize,
+        other: usize,
+        positions: &[DVec3],
+        velocities: &[DVec3],
+        masses: &[f64],
+    ) -> DVec3 {
+        if masses[other] == 0.0 {
+            return DVec3::ZERO;
+        }
+
+        let pull = self.pull(positions[body], positions[other], masses[other]);
+
+        if self.post_newtonian {
+            pull + post_newtonian_correction(
+                positions[body] - positions[other],
+                velocities[body] - velocities[other],
+                masses[other],
+            )
+        } else {
+            pull
+        }
+    }
+
+    /// Acceleration of a body at `position` caused by a mass at `other_position`.
+    /// A massless body pulls nothing, even from the same position
+    pub fn pull(&self, position: DVec3, other_position: DVec3, other_mass: f64) -> DVec3 {
+        if other_mass == 0.0 {
+            return DVec3::ZERO;
+        }
+
+        let separation = other_position - position;
+        let distance = match self.min_distance {
+            Some(min_distance) => separation.length().max(min_distance),
+            None => separation.length(),
+        };
+        let softened_distance_squared = distance * distance + self.softening * self.softening;
+
+        // G * m / r^2 in the direction of the separation vector
+        separation * (G * other_mass / softened_distance_squared.powf(1.5))
+    }
+
+    /// Gravitational potential energy of all the pairs of bodies, in joules, of the same force
+    /// law as [`Self::pull`]. Summed over every pair whatever the solver is, and without
+    /// the relativistic correction
+    pub fn potential_energy(&self, positions: &[DVec3], masses: &[f64]) -> f64 {
+        let mut energy = 0.0;
+
+        for body in 0..positions.len() {
+            for other in body + 1..positions.len() {
+                if masses[body] == 0.0 || masses[other] == 0.0 {
+                    continue;
+                }
+
+                let distance = positions[body].distance(positions[other]);
+
+                energy += masses[body] * masses[other] * self.potential(distance);
+            }
+        }
+
+        energy
+    }
+
+    /// Potential of a unit mass at `distance` from another unit mass, `-G / r` when unsoftened
+    fn potential(&self, distance: f64) -> f64 {
+        let softening_squared = self.softening * self.softening;
+
+        match self.min_distance {
+            // the pull grows linearly from the center up to the minimum distance
+            Some(min_distance) if distance < min_distance => {
+                let edge_squared = min_distance * min_distance + softening_squared;
+
+                -G / edge_squared.sqrt()
+                    - G * (min_distance * min_distance - distance * distance)
+                        / (2.0 * edge_squared.powf(1.5))
+            }
+            _ => -G / (distance * distance + softening_squared).sqrt(),
+        }
+    }
+}
+
+/// 1PN acceleration of a test body at `relative_position` moving with `relative_velocity`
+/// around `mass` (Schwarzschild metric, harmonic coordinates):
+/// `G * M / (c² * r³) * ((4 * G * M / r - v²) * r + 4 * (r · v) * v)`
+fn post_newtonian_correction(
+    relative_position: DVec3,
+    relative_velocity: DVec3,
+    mass: f64,
+) -> DVec3 {
+    let sgp = G * mass;
+    let distance = relative_position.length();
+
+    (relative_position * (4.0 * sgp / distance - relative_velocity.length_squared())
+        + relative_velocity * (4.0 * relative_position.dot(relative_velocity)))
+        * (sgp / (C * C * distance.powi(3)))
+}
+
+/// Calls `acceleration` for every body index, chunk by chunk on the tasks of `pool`.
+/// The results are returned in the order of indices
+fn in_parallel(
+    pool: &TaskPool,
+    count: usize,
+    acceleration: impl Fn(usize) -> DVec3 + Sync,
+) -> Vec<DVec3> {
+    if count <= BODIES_PER_TASK {
+        return (0..count).map(acceleration).collect();
+    }
+
+    let acceleration = &acceleration;
+
+    pool.scope(|scope| {
+        for start in (0..count).step_by(BODIES_PER_TASK) {
+            let end = usize::min(start + BODIES_PER_TASK, count);
+
+            scope.spawn(async move { (start..end).map(acceleration).collect::<Vec<_>>() });
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_tasks::TaskPoolBuilder;
+
+    use super::*;
+
+    #[test]
+    fn accelerations_dont_depend_on_the_thread_count() {
+        let spread = |body: usize, step: f64| (body as f64 * step).fract() - 0.5;
+        let positions: Vec<DVec3> = (0..300)
+            .map(|body| {
+                DVec3::new(
+                    spread(body, 0.618),
+                    spread(body, 0.414),
+                    spread(body, 0.732),
+                )
+            })
+            .map(|position| position * 1e11)
+            .collect();
+        let velocities: Vec<DVec3> = positions
+            .iter()
+            .map(|position| position.cross(DVec3::Z) * 3e-7)
+            .collect();
+        let masses: Vec<f64> = (0..300)
+            .map(|body| 1e24 * (1.0 + spread(body, 0.236)))
+            .collect();
+
+        for gravity in [
+            Gravity {
+                post_newtonian: true,
+                ..Default::default()
+            },
+            Gravity {
+                solver: ForceSolver::BarnesHut { opening_angle: 0.5 },
+                ..Default::default()
+            },
+        ] {
+            let accelerations = |threads: usize| {
+                let pool = TaskPoolBuilder::new().num_threads(threads).build();
+
+                gravity.accelerations(&pool, &positions, &velocities, &masses)
+            };
+            let single_threaded = accelerations(1);
+
+            for threads in [2, 3, 8] {
+                assert_eq!(accelerations(threads), single_threaded);
+            }
+        }
+    }
+}
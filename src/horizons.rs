@@ -0,0 +1,327 @@
+//! Reads the state vectors of the JPL Horizons "VECTORS" ephemerides saved as text,
+//! both the plain table and the CSV one
+
+use std::{fs, path::Path};
+
+use bevy_math::DVec3;
+
+use crate::orbit::{METERS_PER_AU, SECONDS_PER_DAY};
+
+const METERS_PER_KM: f64 = 1e3;
+/// Obliquity of the ecliptic at J2000 used by Horizons, 84381.448″
+const OBLIQUITY: f64 = 84381.448 / 3600.0 * std::f64::consts::PI / 180.0;
+
+/// Position and velocity of the target at an epoch, in meters and meters per second,
+/// in the ecliptic frame of J2000: the XY plane is the ecliptic, X points to the equinox
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HorizonsState {
+    /// Julian date in the TDB time scale
+    pub julian_date: f64,
+    pub position: DVec3,
+    pub velocity: DVec3,
+}
+
+#[derive(Clone, Debug)]
+pub struct HorizonsVectors {
+    /// Like "Earth (399)"
+    pub target: Option<String>,
+    /// Body the vectors are relative to, like "Sun (10)"
+    pub center: Option<String>,
+    pub states: Vec<HorizonsState>,
+}
+
+impl HorizonsVectors {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let lines: Vec<&str> = text.lines().collect();
+
+        let start = lines
+            .iter()
+            .position(|line| line.trim() == "$$SOE")
+            .ok_or("no $$SOE line, expected a Horizons VECTORS table")?;
+        let end = lines[start..]
+            .iter()
+            .position(|line| line.trim() == "$$EOE")
+            .map(|end| start + end)
+            .ok_or("no $$EOE line after $$SOE")?;
+
+        let header = &lines[..start];
+        let (length_unit, time_unit) = units(header)?;
+        let equatorial = is_equatorial(header);
+
+        let rows = &lines[start + 1..end];
+        let is_csv = rows
+            .iter()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| line.contains(','));
+
+        let states = if is_csv {
+            csv_states(header, rows)?
+        } else {
+            table_states(rows)?
+        };
+
+        if states.is_empty() {
+            return Err("there are no states between $$SOE and $$EOE".to_string());
+        }
+
+        let to_ecliptic = |vector: DVec3| {
+            if equatorial {
+                let (sin, cos) = OBLIQUITY.sin_cos();
+                DVec3::new(
+                    vector.x,
+                    cos * vector.y + sin * vector.z,
+                    -sin * vector.y + cos * vector.z,
+                )
+            } else {
+                vector
+            }
+        };
+
+        Ok(Self {
+            target: header_value(header, "Target body name"),
+            center: header_value(header, "Center body name"),
+            states: states
+                .into_iter()
+                .map(|state| HorizonsState {
+                    julian_date: state.julian_date,
+                    position: to_ecliptic(state.position * length_unit),
+                    velocity: to_ecliptic(state.velocity * length_unit / time_unit),
+                })
+                .collect(),
+        })
+    }
+
+    /// The state at the given Julian date, or the first one when there's no date
+    pub fn state_at(&self, julian_date: Option<f64>) -> Option<&HorizonsState> {
+        match julian_date {
+            // tolerates the rounding of the printed dates, a millisecond
+            Some(julian_date) => self
+                .states
+                .iter()
+                .find(|state| (state.julian_date - julian_date).abs() < 1e-8),
+            None => self.states.first(),
+        }
+    }
+}
+
+/// Text after `name:` on the header line starting with it, without the `{source: ...}` part
+fn header_value(header: &[&str], name: &str) -> Option<String> {
+    let line = header
+        .iter()
+        .find(|line| line.trim_start().starts_with(name))?;
+    let (_, value) = line.split_once(':')?;
+    let value = value.split('{').next()?.trim();
+
+    Some(value.to_string())
+}
+
+/// Meters per length unit and seconds per time unit of the "Output units" header
+fn units(header: &[&str]) -> Result<(f64, f64), String> {
+    let units = match header_value(header, "Output units") {
+        Some(units) => units.to_uppercase(),
+        // the default of Horizons
+        None => return Ok((METERS_PER_KM, 1.0)),
+    };
+
+    let length = if units.starts_with("KM") {
+        METERS_PER_KM
+    } else if units.starts_with("AU") {
+        METERS_PER_AU
+    } else {
+        return Err(format!("unknown output units {}", units));
+    };
+
+    let time = if units.contains("-S") {
+        1.0
+    } else if units.contains("-D") {
+        SECONDS_PER_DAY
+    } else {
+        return Err(format!("unknown output units {}", units));
+    };
+
+    Ok((length, time))
+}
+
+/// Whether the vectors are given in the Earth's equator frame instead of the ecliptic one
+fn is_equatorial(header: &[&str]) -> bool {
+    ["Reference plane", "Coordinate systm", "Coordinate system"]
+        .iter()
+        .filter_map(|name| header_value(header, name))
+        .any(|value| {
+            let value = value.to_uppercase();
+            value.contains("EQUATOR") || value == "FRAME"
+        })
+}
+
+/// Entries like
+///
+/// ```text
+/// 2460000.500000000 = A.D. 2023-Feb-25 00:00:00.0000 TDB
+///  X =-1.426E+08 Y = 4.017E+07 Z =-2.370E+03
+///  VX=-8.535E+00 VY=-2.882E+01 VZ= 1.494E-03
+/// ```
+fn table_states(rows: &[&str]) -> Result<Vec<HorizonsState>, String> {
+    let mut states = vec![];
+    let mut entry: Option<(f64, Vec<(String, f64)>)> = None;
+
+    for row in rows.iter().filter(|row| !row.trim().is_empty()) {
+        let starts_entry = row.chars().next().is_some_and(|c| c.is_ascii_digit());
+
+        if starts_entry {
+            if let Some((julian_date, values)) = entry.take() {
+                states.push(state_from_values(julian_date, &values)?);
+            }
+
+            let julian_date = row
+                .split_whitespace()
+                .next()
+                .and_then(|date| date.parse().ok())
+                .ok_or_else(|| format!("can't read the date of \"{}\"", row.trim()))?;
+            entry = Some((julian_date, vec![]));
+        } else if let Some((_, values)) = &mut entry {
+            let spaced = row.replace('=', " = ");
+            let tokens: Vec<&str> = spaced.split_whitespace().collect();
+
+            for window in tokens.windows(3).filter(|window| window[1] == "=") {
+                let value = window[2]
+                    .parse()
+                    .map_err(|_| format!("can't read the {} value \"{}\"", window[0], window[2]))?;
+                values.push((window[0].to_string(), value));
+            }
+        }
+    }
+
+    if let Some((julian_date, values)) = entry {
+        states.push(state_from_values(julian_date, &values)?);
+    }
+
+    Ok(states)
+}
+
+/// Rows like `2460000.500000000, A.D. 2023-Feb-25 00:00:00.0000, -1.4E+08, ...` with the
+/// column names on the header line starting with `JDTDB`
+fn csv_states(header: &[&str], rows: &[&str]) -> Result<Vec<HorizonsState>, String> {
+    let columns: Vec<&str> = header
+        .iter()
+        .rev()
+        .find(|line| line.trim_start().starts_with("JDTDB"))
+        .ok_or("no JDTDB line naming the CSV columns")?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    rows.iter()
+        .filter(|row| !row.trim().is_empty())
+        .map(|row| {
+            let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+            let julian_date = fields[0]
+                .parse()
+                .map_err(|_| format!("can't read the date of \"{}\"", row.trim()))?;
+
+            let values = columns
+                .iter()
+                .zip(&fields)
+                .skip(1)
+                .filter_map(|(column, field)| {
+                    field.parse().ok().map(|value| (column.to_string(), value))
+                })
+                .collect::<Vec<_>>();
+
+            state_from_values(julian_date, &values)
+        })
+        .collect()
+}
+
+fn state_from_values(julian_date: f64, values: &[(String, f64)]) -> Result<HorizonsState, String> {
+    let value = |name: &str| {
+        values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                format!(
+                    "no {} at JD {}, the table must have positions and velocities",
+                    name, julian_date
+                )
+            })
+    };
+
+    Ok(HorizonsState {
+        julian_date,
+        position: DVec3::new(value("X")?, value("Y")?, value("Z")?),
+        velocity: DVec3::new(value("VX")?, value("VY")?, value("VZ")?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+*******************************************************************************
+Target body name: Earth (399)                     {source: DE441}
+Center body name: Sun (10)                        {source: DE441}
+*******************************************************************************
+Reference frame : ICRF
+Reference plane : Ecliptic of J2000.0
+Output units    : KM-S
+*******************************************************************************
+$$SOE
+2460000.500000000 = A.D. 2023-Feb-25 00:00:00.0000 TDB
+ X =-1.426E+08 Y = 4.017E+07 Z =-2.370E+03
+ VX=-8.535E+00 VY=-2.882E+01 VZ= 1.494E-03
+2460001.500000000 = A.D. 2023-Feb-26 00:00:00.0000 TDB
+ X =-1.433E+08 Y = 3.768E+07 Z =-2.240E+03
+ VX=-7.930E+00 VY=-2.899E+01 VZ= 1.526E-03
+$$EOE
+*******************************************************************************
+";
+
+    const CSV: &str = "\
+Target body name: Earth (399)                     {source: DE441}
+Center body name: Sun (10)                        {source: DE441}
+Reference plane : Ecliptic of J2000.0
+Output units    : AU-D
+            JDTDB,            Calendar Date (TDB),                      X,                      Y,                      Z,                     VX,                     VY,                     VZ,
+**************************************************************************************************************************************************************************************************
+$$SOE
+2460000.500000000, A.D. 2023-Feb-25 00:00:00.0000, -9.5E-01,  2.7E-01, -1.6E-05, -4.9E-03, -1.7E-02,  8.6E-07,
+$$EOE
+";
+
+    #[test]
+    fn parses_the_table() {
+        let vectors = HorizonsVectors::parse(TABLE).unwrap();
+
+        assert_eq!(vectors.target.as_deref(), Some("Earth (399)"));
+        assert_eq!(vectors.center.as_deref(), Some("Sun (10)"));
+        assert_eq!(vectors.states.len(), 2);
+
+        let state = vectors.state_at(Some(2460001.5)).unwrap();
+        assert_eq!(state.position, DVec3::new(-1.433e11, 3.768e10, -2.240e6));
+        assert_eq!(state.velocity, DVec3::new(-7.930e3, -2.899e4, 1.526));
+    }
+
+    #[test]
+    fn parses_the_csv() {
+        let vectors = HorizonsVectors::parse(CSV).unwrap();
+        let state = vectors.state_at(None).unwrap();
+
+        assert_eq!(vectors.target.as_deref(), Some("Earth (399)"));
+        assert_eq!(state.julian_date, 2460000.5);
+        assert_eq!(
+            state.position,
+            DVec3::new(-9.5e-1, 2.7e-1, -1.6e-5) * METERS_PER_AU
+        );
+        assert_eq!(
+            state.velocity,
+            DVec3::new(-4.9e-3, -1.7e-2, 8.6e-7) * METERS_PER_AU / SECONDS_PER_DAY
+        );
+    }
+}
@@ -0,0 +1,613 @@
+use bevy_math::DVec3;
+use serde::{Deserialize, Serialize};
+
+/// Numerical scheme used to advance the bodies by one time step
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum Integrator {
+    /// Semi-implicit Euler: the velocity is updated first, then the position is moved with the
+    /// new velocity. First order, kept for comparison with the other schemes
+    Euler,
+    /// Kick-drift-kick leapfrog: half a velocity kick, a full position drift and another half kick
+    /// with the accelerations at the new positions. Second order and symplectic
+    #[default]
+    Leapfrog,
+    /// Velocity Verlet: the position is moved using the current velocity and acceleration, then
+    /// the velocity is advanced with the average of the old and the new accelerations
+    VelocityVerlet,
+    /// Adaptive Dormand–Prince 5(4) Runge–Kutta. Every step is split into substeps whose size is
+    /// chosen from the difference between the embedded 5th and 4th order solutions, so that the
+    /// estimated error of every position and velocity component stays below `tolerance`
+    /// relative to that component
+    DormandPrince {
+        tolerance: f64,
+        /// Substep size to try first on the next step, remembered between steps
+        #[serde(default)]
+        next_step: Option<f64>,
+    },
+}
+
+/// Computes the accelerations of all the bodies at the given positions and velocities
+pub type Accelerations<'a> = dyn Fn(&[DVec3], &[DVec3]) -> Vec<DVec3> + 'a;
+
+/// Accelerations at the positions the last step ended at. Leapfrog and velocity Verlet start
+/// a step with the accelerations they ended the previous one with, so that the forces are
+/// computed once per step instead of twice. They're only reused at exactly the same positions:
+/// moving, adding or removing bodies makes them computed again
+#[derive(Debug, Default)]
+pub struct ForceCache {
+    positions: Vec<DVec3>,
+    accelerations: Vec<DVec3>,
+}
+
+impl ForceCache {
+    /// The cached accelerations when they're at `positions`, they're taken out of the cache
+    fn take(&mut self, positions: &[DVec3]) -> Option<Vec<DVec3>> {
+        if self.positions == positions {
+            self.positions.clear();
+            Some(std::mem::take(&mut self.accelerations))
+        } else {
+            None
+        }
+    }
+
+    fn store(&mut self, positions: &[DVec3], accelerations: &[DVec3]) {
+        self.positions.clear();
+        self.positions.extend_from_slice(positions);
+        self.accelerations.clear();
+        self.accelerations.extend_from_slice(accelerations);
+    }
+}
+
+/// Sizes of the substeps taken while integrating, in seconds (always positive)
+#[derive(Debug, Default)]
+pub struct StepReport {
+    pub accepted: Vec<f64>,
+    pub rejected: usize,
+}
+
+impl StepReport {
+    fn fixed(dt: f64) -> Self {
+        Self {
+            accepted: vec![dt.abs()],
+            rejected: 0,
+        }
+    }
+
+    pub fn append(&mut self, other: Self) {
+        self.accepted.extend(other.accepted);
+        self.rejected += other.rejected;
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.accepted.iter().copied().reduce(f64::min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.accepted.iter().copied().reduce(f64::max)
+    }
+}
+
+impl Integrator {
+    pub fn dormand_prince(tolerance: f64) -> Self {
+        Self::DormandPrince {
+            tolerance,
+            next_step: None,
+        }
+    }
+
+    /// Advances `positions` and `velocities` by `dt` seconds.
+    /// Both slices and the result of `accelerations` are indexed the same way.
+    ///
+    /// The accelerations are carried from one step to the next in `cache`, which must be `None`
+    /// when they depend on the velocities
+    pub fn step(
+        &mut self,
+        positions: &mut [DVec3],
+        velocities: &mut [DVec3],
+        dt: f64,
+        accelerations: &Accelerations,
+        cache: Option<&mut ForceCache>,
+    ) -> StepReport {
+        match self {
+            Self::Euler => euler(positions, velocities, dt, accelerations),
+            Self::Leapfrog => leapfrog(positions, velocities, dt, accelerations, cache),
+            Self::VelocityVerlet => {
+                velocity_verlet(positions, velocities, dt, accelerations, cache)
+            }
+            Self::DormandPrince {
+                tolerance,
+                next_step,
+            } => {
+                return dormand_prince(
+                    positions,
+                    velocities,
+                    dt,
+                    accelerations,
+                    *tolerance,
+                    next_step,
+                )
+            }
+        }
+
+        StepReport::fixed(dt)
+    }
+}
+
+fn euler(
+    positions: &mut [DVec3],
+    velocities: &mut [DVec3],
+    dt: f64,
+    accelerations: &Accelerations,
+) {
+    let accelerations = accelerations(positions, velocities);
+
+    for ((position, velocity), acceleration) in positions
+        .iter_mut()
+        .zip(velocities.iter_mut())
+        .zip(accelerations)
+    {
+        *velocity += acceleration * dt;
+        *position += *velocity * dt;
+    }
+}
+
+fn leapfrog(
+    positions: &mut [DVec3],
+    velocities: &mut [DVec3],
+    dt: f64,
+    accelerations: &Accelerations,
+    mut cache: Option<&mut ForceCache>,
+) {
+    let half_dt = dt / 2.0;
+
+    let first_kick = starting_accelerations(&mut cache, positions, velocities, accelerations);
+    kick(velocities, &first_kick, half_dt);
+
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter()) {
+        *position += *velocity * dt;
+    }
+
+    // velocity dependent forces are evaluated with the half step velocities
+    let second_kick = accelerations(positions, velocities);
+    kick(velocities, &second_kick, half_dt);
+
+    if let Some(cache) = cache {
+        cache.store(positions, &second_kick);
+    }
+}
+
+fn velocity_verlet(
+    positions: &mut [DVec3],
+    velocities: &mut [DVec3],
+    dt: f64,
+    accelerations: &Accelerations,
+    mut cache: Option<&mut ForceCache>,
+) {
+    let old_accelerations =
+        starting_accelerations(&mut cache, positions, velocities, accelerations);
+
+    for ((position, velocity), acceleration) in positions
+        .iter_mut()
+        .zip(velocities.iter())
+        .zip(&old_accelerations)
+    {
+        *position += *velocity * dt + *acceleration * (dt * dt / 2.0);
+    }
+
+    // velocity dependent forces are evaluated with the velocities predicted by the old accelerations
+    let predicted_velocities: Vec<DVec3> = velocities
+        .iter()
+        .zip(&old_accelerations)
+        .map(|(velocity, acceleration)| *velocity + *acceleration * dt)
+        .collect();
+    let new_accelerations = accelerations(positions, &predicted_velocities);
+
+    for ((velocity, old), new) in velocities
+        .iter_mut()
+        .zip(old_accelerations)
+        .zip(&new_accelerations)
+    {
+        *velocity += (old + *new) * (dt / 2.0);
+    }
+
+    if let Some(cache) = cache {
+        cache.store(positions, &new_accelerations);
+    }
+}
+
+/// Accelerations at the start of a step, from `cache` when it has them at `positions`
+fn starting_accelerations(
+    cache: &mut Option<&mut ForceCache>,
+    positions: &[DVec3],
+    velocities: &[DVec3],
+    accelerations: &Accelerations,
+) -> Vec<DVec3> {
+    cache
+        .as_mut()
+        .and_then(|cache| cache.take(positions))
+        .unwrap_or_else(|| accelerations(positions, velocities))
+}
+
+fn kick(velocities: &mut [DVec3], accelerations: &[DVec3], dt: f64) {
+    for (velocity, acceleration) in velocities.iter_mut().zip(accelerations) {
+        *velocity += *acceleration * dt;
+    }
+}
+
+const DP_A: [[f64; 6]; 6] = [
+    [1.0 / 5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0 / 40.0, 9.0 / 40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0 / 45.0, -56.0 / 15.0, 32.0 / 9.0, 0.0, 0.0, 0.0],
+    [
+        19372.0 / 6561.0,
+        -25360.0 / 2187.0,
+        64448.0 / 6561.0,
+        -212.0 / 729.0,
+        0.0,
+        0.0,
+    ],
+    [
+        9017.0 / 3168.0,
+        -355.0 / 33.0,
+        46732.0 / 5247.0,
+        49.0 / 176.0,
+        -5103.0 / 18656.0,
+        0.0,
+    ],
+    [
+        35.0 / 384.0,
+        0.0,
+        500.0 / 1113.0,
+        125.0 / 192.0,
+        -2187.0 / 6784.0,
+        11.0 / 84.0,
+    ],
+];
+/// Difference between the 5th and the 4th order weights
+const DP_ERROR: [f64; 7] = [
+    71.0 / 57600.0,
+    0.0,
+    -71.0 / 16695.0,
+    71.0 / 1920.0,
+    -17253.0 / 339200.0,
+    22.0 / 525.0,
+    -1.0 / 40.0,
+];
+
+const SAFETY_FACTOR: f64 = 0.9;
+const MIN_STEP_FACTOR: f64 = 0.2;
+const MAX_STEP_FACTOR: f64 = 5.0;
+/// Part of the largest position or velocity of the system the error of every component
+/// is allowed on top of its relative error
+const ABSOLUTE_SCALE_FRACTION: f64 = 1e-6;
+/// Substeps shorter than this part of the whole step are accepted regardless of the error,
+/// so that a close encounter can't stall the simulation
+const MIN_STEP_FRACTION: f64 = 1e-6;
+
+fn dormand_prince(
+    positions: &mut [DVec3],
+    velocities: &mut [DVec3],
+    dt: f64,
+    accelerations: &Accelerations,
+    tolerance: f64,
+    next_step: &mut Option<f64>,
+) -> StepReport {
+    // substep sizes are kept positive, `direction` is negative when integrating backwards
+    let direction = dt.signum();
+    let span = dt.abs();
+
+    let mut report = StepReport::default();
+    let mut elapsed = 0.0;
+    let mut step = next_step.unwrap_or(span).min(span);
+
+    while span - elapsed > span * MIN_STEP_FRACTION {
+        let remaining = span - elapsed;
+        // a substep ending closer to the end than the shortest one is stretched to the end,
+        // the loop would stop before integrating the rest otherwise
+        let truncated = step >= remaining - span * MIN_STEP_FRACTION;
+        let h = if truncated { remaining } else { step };
+
+        let (new_positions, new_velocities, error) =
+            dormand_prince_trial(positions, velocities, h * direction, accelerations);
+        let error = error / tolerance;
+        // a non-finite state can't be fixed by shrinking the step, it's accepted and left
+        // for the caller to deal with
+        let accepted = error <= 1.0 || error.is_nan() || h <= span * MIN_STEP_FRACTION;
+
+        if accepted {
+            positions.copy_from_slice(&new_positions);
+            velocities.copy_from_slice(&new_velocities);
+            elapsed += h;
+            report.accepted.push(h);
+        } else {
+            report.rejected += 1;
+        }
+
+        let factor = if error > 0.0 {
+            (SAFETY_FACTOR * error.powf(-0.2)).clamp(MIN_STEP_FACTOR, MAX_STEP_FACTOR)
+        } else {
+            MAX_STEP_FACTOR
+        };
+        let proposed = h * factor;
+
+        step = if truncated && accepted {
+            step.max(proposed)
+        } else {
+            proposed
+        };
+    }
+
+    *next_step = Some(step);
+
+    report
+}
+
+/// Takes a single Dormand–Prince step of `h` seconds.
+/// Returns the new positions and velocities along with the error norm of the step
+fn dormand_prince_trial(
+    positions: &[DVec3],
+    velocities: &[DVec3],
+    h: f64,
+    accelerations: &Accelerations,
+) -> (Vec<DVec3>, Vec<DVec3>, f64) {
+    // derivatives of positions (velocities) and of velocities (accelerations) at every stage
+    let mut position_rates: Vec<Vec<DVec3>> = vec![velocities.to_vec()];
+    let mut velocity_rates: Vec<Vec<DVec3>> = vec![accelerations(positions, velocities)];
+
+    let mut new_positions = vec![];
+    let mut new_velocities = vec![];
+
+    for weights in DP_A.iter() {
+        new_positions = combine(positions, &position_rates, weights, h);
+        new_velocities = combine(velocities, &velocity_rates, weights, h);
+
+        position_rates.push(new_velocities.clone());
+        velocity_rates.push(accelerations(&new_positions, &new_velocities));
+    }
+
+    // the last stage is evaluated at the 5th order solution
+    let position_errors = combine(
+        &vec![DVec3::ZERO; positions.len()],
+        &position_rates,
+        &DP_ERROR,
+        h,
+    );
+    let velocity_errors = combine(
+        &vec![DVec3::ZERO; velocities.len()],
+        &velocity_rates,
+        &DP_ERROR,
+        h,
+    );
+
+    let error = f64::max(
+        scaled_error(positions, &new_positions, &position_errors),
+        scaled_error(velocities, &new_velocities, &velocity_errors),
+    );
+
+    (new_positions, new_velocities, error)
+}
+
+/// `initial + h * Σ weights[stage] * rates[stage]` for every body
+fn combine(initial: &[DVec3], rates: &[Vec<DVec3>], weights: &[f64], h: f64) -> Vec<DVec3> {
+    initial
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            rates
+                .iter()
+                .zip(weights)
+                .fold(*value, |total, (stage, weight)| {
+                    total + stage[index] * (h * weight)
+                })
+        })
+        .collect()
+}
+
+/// Largest error of a component relative to the size of that component before or after
+/// the step. Components much smaller than the largest vector of the system, like the position
+/// of a body resting at the origin, are measured against a floor instead
+fn scaled_error(old: &[DVec3], new: &[DVec3], errors: &[DVec3]) -> f64 {
+    let floor =
+        (ABSOLUTE_SCALE_FRACTION * max_length(old).max(max_length(new))).max(f64::MIN_POSITIVE);
+
+    old.iter()
+        .zip(new)
+        .zip(errors)
+        .map(|((old, new), error)| {
+            (error.abs() / (old.abs().max(new.abs()) + DVec3::splat(floor))).max_element()
+        })
+        .fold(0.0, f64::max)
+}
+
+fn max_length(vectors: &[DVec3]) -> f64 {
+    vectors
+        .iter()
+        .map(|vector| vector.length())
+        .fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, f64::consts::TAU};
+
+    use super::*;
+
+    /// A body around a fixed unit mass with `G = 1`, on an orbit with `a = 1` and `e = 0.5`
+    /// starting at the periapsis, so that its period is 2π
+    fn kepler_orbit() -> (Vec<DVec3>, Vec<DVec3>) {
+        let eccentricity: f64 = 0.5;
+        let speed = ((1.0 + eccentricity) / (1.0 - eccentricity)).sqrt();
+
+        (
+            vec![DVec3::new(1.0 - eccentricity, 0.0, 0.0)],
+            vec![DVec3::new(0.0, speed, 0.0)],
+        )
+    }
+
+    fn kepler_accelerations(positions: &[DVec3], _: &[DVec3]) -> Vec<DVec3> {
+        positions
+            .iter()
+            .map(|position| -*position / position.length().powi(3))
+            .collect()
+    }
+
+    fn energy(positions: &[DVec3], velocities: &[DVec3]) -> f64 {
+        velocities[0].length_squared() / 2.0 - 1.0 / positions[0].length()
+    }
+
+    /// Largest relative energy error over the first and over the last of 100 orbits
+    fn energy_errors(mut integrator: Integrator) -> (f64, f64) {
+        let (mut positions, mut velocities) = kepler_orbit();
+        let initial = energy(&positions, &velocities);
+        let steps_per_orbit = 1000;
+        let dt = TAU / steps_per_orbit as f64;
+
+        let mut first = 0.0;
+        let mut last = 0.0;
+
+        for orbit in 0..100 {
+            for _ in 0..steps_per_orbit {
+                integrator.step(
+                    &mut positions,
+                    &mut velocities,
+                    dt,
+                    &kepler_accelerations,
+                    None,
+                );
+                let error = ((energy(&positions, &velocities) - initial) / initial).abs();
+
+                if orbit == 0 {
+                    first = f64::max(first, error);
+                }
+                if orbit == 99 {
+                    last = f64::max(last, error);
+                }
+            }
+        }
+
+        (first, last)
+    }
+
+    #[test]
+    fn symplectic_integrators_keep_the_energy_bounded() {
+        let (leapfrog, _) = energy_errors(Integrator::Leapfrog);
+
+        for integrator in [
+            Integrator::Leapfrog,
+            Integrator::VelocityVerlet,
+            Integrator::Euler,
+        ] {
+            let (first, last) = energy_errors(integrator);
+
+            // the error oscillates along the orbit, it doesn't build up
+            assert!(
+                last < first * 1.01,
+                "{:?}: {} then {}",
+                integrator,
+                first,
+                last
+            );
+        }
+
+        // semi-implicit Euler is symplectic too, but only of the first order
+        let (euler, _) = energy_errors(Integrator::Euler);
+        assert!(leapfrog < 2e-4, "{}", leapfrog);
+        assert!(euler > 50.0 * leapfrog, "{} {}", euler, leapfrog);
+    }
+
+    #[test]
+    fn runge_kutta_energy_drifts() {
+        let (first, last) = energy_errors(Integrator::dormand_prince(1e-8));
+
+        assert!(last > 10.0 * first, "{} then {}", first, last);
+    }
+
+    #[test]
+    fn cached_accelerations_give_the_same_orbit() {
+        for integrator in [Integrator::Leapfrog, Integrator::VelocityVerlet] {
+            let evaluations = Cell::new(0);
+            let accelerations = |positions: &[DVec3], velocities: &[DVec3]| {
+                evaluations.set(evaluations.get() + 1);
+                kepler_accelerations(positions, velocities)
+            };
+
+            let (mut positions, mut velocities) = kepler_orbit();
+            let (mut cached_positions, mut cached_velocities) = kepler_orbit();
+            let mut uncached = integrator;
+            let mut cached = integrator;
+            let mut cache = ForceCache::default();
+            let mut cached_evaluations = 0;
+
+            for _ in 0..1000 {
+                uncached.step(&mut positions, &mut velocities, 0.01, &accelerations, None);
+
+                let before = evaluations.get();
+                cached.step(
+                    &mut cached_positions,
+                    &mut cached_velocities,
+                    0.01,
+                    &accelerations,
+                    Some(&mut cache),
+                );
+                cached_evaluations += evaluations.get() - before;
+            }
+
+            assert_eq!(cached_positions, positions);
+            assert_eq!(cached_velocities, velocities);
+            // only the first step computes the starting accelerations
+            assert_eq!(cached_evaluations, 1001);
+        }
+    }
+
+    #[test]
+    fn dormand_prince_meets_its_tolerance_both_ways() {
+        let (start_positions, start_velocities) = kepler_orbit();
+        let (mut positions, mut velocities) = kepler_orbit();
+        let mut integrator = Integrator::dormand_prince(1e-10);
+
+        let report = integrator.step(
+            &mut positions,
+            &mut velocities,
+            TAU,
+            &kepler_accelerations,
+            None,
+        );
+
+        assert!(report.accepted.len() > 10);
+        assert_eq!(report.accepted.iter().sum::<f64>(), TAU);
+        // back at the periapsis after a period
+        assert!((positions[0] - start_positions[0]).length() < 1e-8);
+        assert!((velocities[0] - start_velocities[0]).length() < 1e-8);
+
+        integrator.step(
+            &mut positions,
+            &mut velocities,
+            -TAU,
+            &kepler_accelerations,
+            None,
+        );
+
+        assert!((positions[0] - start_positions[0]).length() < 1e-8);
+        assert!((velocities[0] - start_velocities[0]).length() < 1e-8);
+    }
+
+    #[test]
+    fn dormand_prince_integrates_up_to_the_end_of_the_step() {
+        let (mut positions, mut velocities) = kepler_orbit();
+        // falls short of the step by less than the shortest substep
+        let mut integrator = Integrator::DormandPrince {
+            tolerance: 1e-3,
+            next_step: Some(0.01 * (1.0 - 1e-7)),
+        };
+
+        let report = integrator.step(
+            &mut positions,
+            &mut velocities,
+            0.01,
+            &kepler_accelerations,
+            None,
+        );
+
+        assert_eq!(report.accepted, vec![0.01]);
+    }
+}
@@ -0,0 +1,19 @@
+//! Physics of the planetary simulation, independent of the Bevy frontend:
+//! the body state, force solvers, integrators and orbital math
+
+pub mod barnes_hut;
+pub mod clock;
+pub mod collision;
+pub mod conservation;
+pub mod gravity;
+pub mod horizons;
+pub mod integrator;
+pub mod maneuver;
+pub mod momentum;
+pub mod orbit;
+pub mod particles;
+pub mod precession;
+pub mod scenario;
+pub mod simulation;
+pub mod snapshot;
+pub mod trajectory;
@@ -1,46 +1,226 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub};
-
-use bevy::{core::FixedTimestep, input::mouse::MouseWheel, prelude::*};
+use std::{
+    env,
+    f64::consts::TAU,
+    fs::File,
+    io::BufWriter,
+    ops::{Add, Div, DivAssign, Mul, MulAssign, Sub},
+    process,
+};
+
+use bevy::{
+    ecs::schedule::ShouldRun, input::mouse::MouseWheel, math::DVec3, prelude::*,
+    tasks::ComputeTaskPool,
+};
 use bevy_prototype_lyon::prelude::*;
-use ui::{toggle_switch::ToggleState, AddSunToggle, ShowTracesToggle};
+use planetary_simulation_rs::{
+    barnes_hut,
+    clock::{parse_time, SimulationClock},
+    collision::BodyState,
+    conservation::{ConservationMonitor, DiagnosticsWriter, DEFAULT_DRIFT_THRESHOLD},
+    gravity::{ForceSolver, Gravity},
+    integrator::{ForceCache, Integrator, StepReport},
+    maneuver::FlightPlan,
+    momentum::{BarycentricFrame, CenterOfMass},
+    orbit::{self, Elements, OrbitPrimary, Reference, METERS_PER_AU, SECONDS_PER_DAY},
+    particles::TestParticles,
+    precession::ApsidalPrecession,
+    scenario::{Scenario, ScenarioBody},
+    simulation::{merge_bodies, Body, Dynamics},
+    snapshot::Snapshot,
+    trajectory::TrajectoryRecorder,
+};
+use ui::{
+    toggle_switch::ToggleState, AddSunToggle, DeltaVBudget, PostNewtonianToggle, ShowTracesToggle,
+};
+
+mod ui;
+
+/// Physics steps per real second at the start
+const STEPS_PER_SECOND: f64 = 1000.0;
+/// Physics steps between two trace points
+const STEPS_PER_TRACE_POINT: f64 = 240.0;
+/// Physics steps between two redraws of the test particles
+const STEPS_PER_PARTICLE_DRAW: f64 = 60.0;
+/// F5 saves the simulation into this file, F9 loads it back
+const SNAPSHOT_PATH: &str = "snapshot.ron";
+
+const USAGE: &str = "\
+Usage: planetary-simulation-rs [scenario] [options]
+
+The scenario is a .ron, .toml or .json file, the bundled solar system is used when there's none
+
+Options:
+    --record <file>         write the trajectories into a .csv or .ndjson file
+    --record-every <time>   simulated time between two recorded samples [default: 1d]
+    --elements-around <body>
+                            add the osculating orbital elements around this body to the records
+    --diagnostics <file>    write the energy, momentum and angular momentum with their drift
+                            into a .csv or .ndjson file whenever they're sampled
+    --drift-threshold <number>
+                            relative drift of a conserved quantity that is warned about [default: 1e-6]
+    --precession-of <body>  body whose periapsis precession is measured [default: Mercury]
+
+Times are in seconds, or with a unit: 30m, 12h, 10d, 2y";
+
+struct Arguments {
+    scenario: Option<String>,
+    record: Option<String>,
+    record_every: f64,
+    elements_around: Option<String>,
+    diagnostics: Option<String>,
+    drift_threshold: f64,
+    precession_of: String,
+}
 
-pub mod ui;
+fn main() {
+    let arguments = parse_arguments(env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}\n\n{}", message, USAGE);
+        process::exit(2);
+    });
 
-const CALCULATE_TIME_STEP: f32 = 0.001;
-const DRAW_TIME_STEP: f32 = CALCULATE_TIME_STEP * 240.0;
+    let exit_on_error = |path: &str, error: &dyn std::fmt::Display| -> ! {
+        eprintln!("{}: {}", path, error);
+        process::exit(1);
+    };
+
+    let scenario = match &arguments.scenario {
+        Some(path) => Scenario::load(path).unwrap_or_else(|error| exit_on_error(path, &error)),
+        None => Scenario::solar_system(),
+    };
+
+    let frame = BarycentricFrame::following(
+        &CenterOfMass::of_bodies(&scenario.bodies()).expect("scenarios have bodies with mass"),
+        0.0,
+    );
+
+    let particles = scenario.particles();
+
+    let recorder = arguments.record.as_ref().map(|path| {
+        let mut recorder = TrajectoryRecorder::create(path, arguments.record_every)
+            .unwrap_or_else(|error| exit_on_error(path, &error));
+        recorder.elements_around = arguments.elements_around.clone();
+
+        recorder
+    });
+
+    let diagnostics = arguments.diagnostics.as_ref().map(|path| {
+        DiagnosticsWriter::create(path).unwrap_or_else(|error| exit_on_error(path, &error))
+    });
 
-fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugin(ShapePlugin)
         .add_plugin(ui::UiPlugin)
-        .init_resource::<ViewScale>()
+        .insert_resource(ViewScale(scenario.view_scale.unwrap_or(INIT_SCALE)))
+        .insert_resource(scenario.integrator)
+        .insert_resource(scenario.gravity.clone())
+        .init_resource::<StepReport>()
+        .init_resource::<ForceCache>()
+        .insert_resource(SimulationClock::new(
+            scenario.time_step,
+            scenario.time_step * STEPS_PER_SECOND,
+        ))
+        .insert_resource(scenario)
+        .insert_resource(particles)
+        .insert_resource(TrajectoryRecording(recorder))
+        .insert_resource(ConservationMonitor::new(arguments.drift_threshold))
+        .insert_resource(DiagnosticsRecording(diagnostics))
+        .insert_resource(PrecessionMeter::new(arguments.precession_of))
+        .insert_resource(KeptFrame(frame))
+        .add_event::<BodiesChanged>()
         .add_event::<NewTracePointDrawn>()
-        .add_event::<NeedToAdjustSunVelocity>()
+        .add_event::<BodiesCollided>()
+        .add_event::<StateDiverged>()
         .add_startup_system(setup.system())
-        .add_system_to_stage(CoreStage::PreUpdate, adjust_sun_velocity.system())
+        .add_system_to_stage(CoreStage::PreUpdate, tick_simulation_clock.system())
         .add_system(zoom_view.system().label("zoom view"))
         .add_system(scale_object_sizes.system().after("zoom view"))
         .add_system(update_scale_line.system().after("zoom view"))
         .add_system(zoom_trace_lines.system().after("zoom view"))
-        .add_system(
-            calculate_new_state
-                .system()
-                .with_run_criteria(FixedTimestep::step(CALCULATE_TIME_STEP as f64)),
+        .add_system_set(
+            SystemSet::new()
+                .with_run_criteria(physics_step_due.system())
+                .with_system(calculate_new_state.system().label("calculate"))
+                .with_system(merge_collided_bodies.system().after("calculate"))
+                .with_system(record_trajectory.system().after("calculate")),
         )
         .add_system_set(
             SystemSet::new()
-                .with_run_criteria(FixedTimestep::step(DRAW_TIME_STEP as f64))
+                .with_run_criteria(trace_interval_passed.system())
                 .with_system(set_baricenters.system())
+                .with_system(report_step_sizes.system())
+                .with_system(measure_precession.system())
+                .with_system(check_conservation.system())
                 // .with_system(list_objects.system().label("list"))
                 .with_system(draw_trace_point.system()),
         )
         .add_system(add_remove_sun.system())
         .add_system(add_remove_traces.system())
+        .add_system(switch_post_newtonian.system())
         .add_system(on_new_trace_point.system())
+        .add_system(log_collisions.system())
+        .add_system(log_diverged_states.system())
+        .add_system(control_simulation_clock.system())
+        .add_system(report_orbits.system())
+        .add_system(report_conservation.system())
+        .add_system(report_force_error.system())
+        .add_system(update_delta_v_budget.system())
+        .add_system(report_delta_v_budget.system())
+        .add_system(rebase_conservation.system())
+        .add_system(save_snapshot.system())
+        .add_system(load_snapshot.system())
+        .add_system(recenter_on_barycenter.system())
+        .add_system(draw_particles.system())
+        .add_system_to_stage(CoreStage::PostUpdate, keep_center_of_mass.system())
         .run();
 }
 
+fn parse_arguments(mut args: impl Iterator<Item = String>) -> Result<Arguments, String> {
+    let mut arguments = Arguments {
+        scenario: None,
+        record: None,
+        record_every: SECONDS_PER_DAY,
+        elements_around: None,
+        diagnostics: None,
+        drift_threshold: DEFAULT_DRIFT_THRESHOLD,
+        precession_of: "Mercury".to_string(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value of {}", arg))
+        };
+
+        match arg.as_str() {
+            "--record" => arguments.record = Some(value()?),
+            "--record-every" => arguments.record_every = parse_time(&value()?)?,
+            "--elements-around" => arguments.elements_around = Some(value()?),
+            "--diagnostics" => arguments.diagnostics = Some(value()?),
+            "--drift-threshold" => {
+                let value = value()?;
+                arguments.drift_threshold = value
+                    .parse()
+                    .map_err(|_| format!("Invalid drift threshold: {}", value))?;
+            }
+            "--precession-of" => arguments.precession_of = value()?,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            other if other.starts_with("--") => return Err(format!("Unknown argument: {}", other)),
+            _ if arguments.scenario.is_none() => arguments.scenario = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument: {}", other)),
+        }
+    }
+
+    if arguments.record_every <= 0.0 {
+        return Err("Times must be positive".to_string());
+    }
+
+    Ok(arguments)
+}
+
 const MAX_SCALE_LINE_LENGTH: f32 = 200.0;
 
 #[derive(Component, Debug)]
@@ -51,7 +231,7 @@ struct ScaleRuler {
 }
 
 #[derive(Clone, Component, Debug, Default)]
-struct Position(Vec3);
+struct Position(DVec3);
 
 impl Sub for &Position {
     type Output = Position;
@@ -101,38 +281,30 @@ impl Add<&Self> for Position {
     }
 }
 
-impl Mul<f32> for Position {
+impl Mul<f64> for Position {
     type Output = Self;
 
-    fn mul(self, other: f32) -> Self::Output {
+    fn mul(self, other: f64) -> Self::Output {
         Self(self.0 * other)
     }
 }
 
-impl Div<f32> for Position {
+impl Div<f64> for Position {
     type Output = Self;
 
-    fn div(self, other: f32) -> Self::Output {
+    fn div(self, other: f64) -> Self::Output {
         Self(self.0 / other)
     }
 }
 
-impl Mul<&Res<'_, ViewScale>> for Position {
-    type Output = Self;
-
-    fn mul(self, other: &Res<'_, ViewScale>) -> Self::Output {
-        Self(self.0 * other.0)
-    }
-}
-
-impl From<Position> for Vec3 {
+impl From<Position> for DVec3 {
     fn from(position: Position) -> Self {
         position.0
     }
 }
 
 #[derive(Clone, Component)]
-struct Velocity(Vec3);
+struct Velocity(DVec3);
 
 impl Sub for &Velocity {
     type Output = Velocity;
@@ -148,19 +320,15 @@ struct Star;
 #[derive(Component)]
 struct Planet;
 
-enum SystemChanged {
-    BodyAdded,
-    BodyRemoved,
-}
+/// Massless body, which can have maneuvers
+#[derive(Component)]
+struct Spacecraft;
 
-struct NeedToAdjustSunVelocity {
-    change: SystemChanged,
-    mass: Mass,
-    velocity: Velocity,
-}
+#[derive(Component)]
+struct Maneuvers(FlightPlan);
 
 #[derive(Clone, Component)]
-struct Mass(f32);
+struct Mass(f64);
 
 impl Add for &Mass {
     type Output = Mass;
@@ -179,15 +347,48 @@ impl Add for Mass {
 }
 
 impl Div for &Mass {
-    type Output = f32;
+    type Output = f64;
 
     fn div(self, other: Self) -> Self::Output {
         self.0 / other.0
     }
 }
 
-#[derive(Component)]
-struct Diameter(f32);
+#[derive(Clone, Component)]
+struct Diameter(f64);
+
+/// Measures the apsidal precession of the body with the given name around its primary
+struct PrecessionMeter {
+    body: String,
+    measurement: ApsidalPrecession,
+}
+
+impl PrecessionMeter {
+    fn new(body: String) -> Self {
+        Self {
+            body,
+            measurement: ApsidalPrecession::default(),
+        }
+    }
+}
+
+/// Every this many samples the measured precession rate is logged
+const PRECESSION_REPORT_SAMPLES: usize = 100;
+
+/// The position or the velocity of a body became infinite or NaN. The step that led to it
+/// is discarded and the simulation is paused
+#[derive(Debug)]
+struct StateDiverged {
+    name: String,
+}
+
+/// Two bodies collided and were merged into `merged`
+#[derive(Debug)]
+struct BodiesCollided {
+    first: String,
+    second: String,
+    merged: Entity,
+}
 
 #[derive(Component, Debug)]
 struct TraceLine {
@@ -230,25 +431,44 @@ struct NewTracePointDrawn {
 #[derive(Component)]
 struct ViewScale(f32);
 
-impl Default for ViewScale {
-    fn default() -> Self {
-        Self(INIT_SCALE)
+/// Writes the trajectories into a file when the app was asked to
+struct TrajectoryRecording(Option<TrajectoryRecorder<BufWriter<File>>>);
+
+/// Writes the conservation samples into a file when the app was asked to
+struct DiagnosticsRecording(Option<DiagnosticsWriter<BufWriter<File>>>);
+
+impl ViewScale {
+    /// Converts a physical position (in meters) into a position on the screen
+    fn to_screen(&self, position: DVec3) -> Vec2 {
+        (position * self.0 as f64).truncate().as_vec2()
+    }
+
+    /// Converts a physical length (in meters) into a length on the screen
+    fn length_to_screen(&self, length: f64) -> f32 {
+        (length * self.0 as f64) as f32
     }
 }
 
-const G: f32 = 6.67e-11;
-const SUN_SGP: f32 = 1.32712440019e20; // Standard gravitational parameter
-const TIME_INTERVAL: f32 = 3600.0;
-const ZERO_ANGLE: Vec3 = Vec3::X;
+/// Points the outline of an orbit is drawn with
+const ORBIT_POINTS: usize = 128;
+/// Open orbits are drawn up to this many times the distance of the body or the periapsis
+const OPEN_ORBIT_EXTENT: f64 = 3.0;
+const PERIAPSIS_MARKER_SIZE: f32 = 3.0;
+const PARTICLE_SIZE: f32 = 0.5;
 const INIT_SCALE: f32 = 500.0 / 260e9;
 const SCALE_CHANGE_BY: f32 = 1.3;
 
 const MIN_STAR_SIZE: f32 = 4.0;
+const SPACECRAFT_SIZE: f32 = 3.0;
 // const MIN_PLANET_SIZE: f32 = 2.0;
 
 #[derive(Clone, Component, Debug)]
 struct Name(String);
 
+/// Name of the scenario body an entity was spawned from, which stays when it's renamed by a merge
+#[derive(Clone, Component, Debug)]
+struct ScenarioName(String);
+
 #[allow(unused)]
 fn list_objects(query: Query<(&Name, &Position, &Velocity), With<Mass>>) {
     for (name, position, velocity) in query.iter() {
@@ -288,9 +508,33 @@ fn add_remove_traces(
     }
 }
 
+fn switch_post_newtonian(
+    mut gravity: ResMut<Gravity>,
+    mut precession_meter: ResMut<PrecessionMeter>,
+    toggle_query: Query<&ToggleState, (With<PostNewtonianToggle>, Changed<ToggleState>)>,
+) {
+    if toggle_query.is_empty() {
+        return;
+    }
+
+    let turn_on = toggle_query.single().0;
+
+    if gravity.post_newtonian != turn_on {
+        gravity.post_newtonian = turn_on;
+        precession_meter.measurement.reset();
+        info!(
+            "Post-Newtonian correction turned {}",
+            if turn_on { "on" } else { "off" }
+        );
+    }
+}
+
+/// Removes the stars, or adds them back as they are at the start of the scenario
 fn add_remove_sun(
     mut commands: Commands,
     view_scale: Res<ViewScale>,
+    scenario: Res<Scenario>,
+    mut bodies_changed: EventWriter<BodiesChanged>,
     sun_query: Query<Entity, With<Star>>,
     toggle_query: Query<&ToggleState, (With<AddSunToggle>, Changed<ToggleState>)>,
 ) {
@@ -302,11 +546,64 @@ fn add_remove_sun(
     let is_sun_present = !sun_query.is_empty();
 
     if is_sun_present && !toggle.0 {
-        let sun = sun_query.single();
-        commands.entity(sun).despawn();
+        sun_query.for_each(|sun| commands.entity(sun).despawn());
+        bodies_changed.send(BodiesChanged);
     }
     if !is_sun_present && toggle.0 {
-        add_sun(commands, &view_scale);
+        add_stars(commands, &view_scale, &scenario);
+        bodies_changed.send(BodiesChanged);
+    }
+}
+
+/// Frame the center of mass of the bodies is kept still in
+struct KeptFrame(BarycentricFrame);
+
+/// Sent when bodies were added, removed or edited, not when the physics moved them
+struct BodiesChanged;
+
+/// Moves all the bodies together so that the center of mass stays in the kept frame,
+/// with no momentum relative to it. Runs after the commands adding or removing bodies are applied
+fn keep_center_of_mass(
+    mut bodies_changed: EventReader<BodiesChanged>,
+    frame: Res<KeptFrame>,
+    clock: Res<SimulationClock>,
+    view_scale: Res<ViewScale>,
+    mut particles: ResMut<TestParticles>,
+    mut query: Query<(&mut Position, &mut Velocity, &Mass, &mut Transform)>,
+) {
+    if bodies_changed.iter().count() == 0 {
+        return;
+    }
+
+    let center = CenterOfMass::of(
+        query
+            .iter_mut()
+            .map(|(position, velocity, mass, _)| (position.0, velocity.0, mass.0)),
+    );
+    let (position_shift, velocity_shift) = match center {
+        Some(center) => frame.0.correction(&center, clock.elapsed),
+        None => return,
+    };
+
+    for (mut position, mut velocity, _, mut transform) in query.iter_mut() {
+        position.0 += position_shift;
+        velocity.0 += velocity_shift;
+        transform.translation = (view_scale.to_screen(position.0), transform.translation.z).into();
+    }
+
+    particles.shift(position_shift, velocity_shift);
+}
+
+/// C moves the center of mass to the origin and stops it there
+fn recenter_on_barycenter(
+    keys: Res<Input<KeyCode>>,
+    mut frame: ResMut<KeptFrame>,
+    mut bodies_changed: EventWriter<BodiesChanged>,
+) {
+    if keys.just_pressed(KeyCode::C) {
+        frame.0 = BarycentricFrame::default();
+        bodies_changed.send(BodiesChanged);
+        info!("Centered the system on its barycenter");
     }
 }
 
@@ -330,30 +627,26 @@ fn zoom_trace_lines(
     }
 
     for (mut transform, position) in query.iter_mut() {
-        transform.translation = (
-            position.0.mul(view_scale.0).truncate(),
-            transform.translation.z,
-        )
-            .into();
+        transform.translation = (view_scale.to_screen(position.0), transform.translation.z).into();
     }
 }
 
 fn scale_object_sizes(
     view_scale: Res<ViewScale>,
-    mut query: Query<(&Diameter, &mut Transform, &Name)>,
+    mut query: Query<(&Diameter, ChangeTrackers<Diameter>, &mut Transform, &Name)>,
 ) {
-    if !view_scale.is_changed() {
-        return;
-    }
+    for (diameter, diameter_tracker, mut transform, name) in query.iter_mut() {
+        if !view_scale.is_changed() && !diameter_tracker.is_changed() {
+            continue;
+        }
 
-    for (diameter, mut transform, name) in query.iter_mut() {
-        let calculated = diameter.0 * view_scale.0;
+        let calculated = view_scale.length_to_screen(diameter.0);
 
         if calculated > MIN_STAR_SIZE {
             let seen = calculated / MIN_STAR_SIZE;
             transform.scale = Vec3::new(seen, seen, seen)
         }
-        println!("{}: scale {}", name.0, transform.scale);
+        debug!("{}: scale {}", name.0, transform.scale);
     }
 }
 
@@ -387,90 +680,550 @@ fn update_scale_line(view_scale: Res<ViewScale>, mut query: Query<&mut ScaleRule
     }
 }
 
-fn adjust_sun_velocity(
-    mut adjust_sun_velocity_event: EventReader<NeedToAdjustSunVelocity>,
-    mut query: Query<(&mut Velocity, &Mass), With<Star>>,
+#[allow(clippy::too_many_arguments)]
+fn calculate_new_state(
+    mut integrator: ResMut<Integrator>,
+    gravity: Res<Gravity>,
+    mut force_cache: ResMut<ForceCache>,
+    pool: Res<ComputeTaskPool>,
+    mut clock: ResMut<SimulationClock>,
+    mut step_report: ResMut<StepReport>,
+    mut state_diverged_event: EventWriter<StateDiverged>,
+    mut particles: ResMut<TestParticles>,
+    mut query: Query<(&Name, &mut Position, &mut Velocity, &Mass, &mut Transform)>,
+    mut maneuvers_query: Query<&mut Maneuvers>,
+    view_scale: Res<ViewScale>,
 ) {
-    if query.is_empty() {
-        return;
+    let dt = clock.step_size();
+
+    let mut positions = vec![];
+    let mut velocities = vec![];
+    let mut masses = vec![];
+
+    for (_, position, velocity, mass, _) in query.iter_mut() {
+        positions.push(position.0);
+        velocities.push(velocity.0);
+        masses.push(mass.0);
     }
 
-    let (mut velocity, mass) = query.single_mut();
+    let mut flight_plans: Vec<FlightPlan> = maneuvers_query
+        .iter_mut()
+        .map(|maneuvers| maneuvers.0.clone())
+        .collect();
+
+    let dynamics = Dynamics {
+        gravity: &*gravity,
+        integrator: &mut *integrator,
+        force_cache: &mut *force_cache,
+        particles: &mut *particles,
+        flight_plans: &mut flight_plans,
+        elapsed: clock.elapsed,
+    };
+    let step = dynamics.step(&pool, dt, &mut positions, &mut velocities, &masses, || {
+        query.iter_mut().map(|(name, ..)| name.0.clone()).collect()
+    });
 
-    for event in adjust_sun_velocity_event.iter() {
-        let velocity_diff = event.velocity.0 * event.mass.0 / mass.0;
+    let outcome = match step {
+        Ok(outcome) => outcome,
+        Err(diverged) => {
+            for name in diverged.names {
+                state_diverged_event.send(StateDiverged { name });
+            }
+            clock.pause();
+            return;
+        }
+    };
 
-        let sign = match event.change {
-            SystemChanged::BodyAdded => -1.0,
-            SystemChanged::BodyRemoved => 1.0,
-        };
+    clock.advance(dt);
+    step_report.append(outcome.report);
 
-        info!("Sun's velocity adjusted: {:?}", sign * velocity_diff);
+    if !outcome.burns.is_empty() {
+        for (mut maneuvers, flight_plan) in maneuvers_query.iter_mut().zip(flight_plans) {
+            maneuvers.0 = flight_plan;
+        }
+    }
+    for burn in outcome.burns {
+        info!(
+            "{} burned {:.1} m/s at {:.2} days",
+            burn.spacecraft,
+            burn.velocity_change.length(),
+            burn.time / SECONDS_PER_DAY
+        );
+    }
 
-        velocity.0.add_assign(sign * velocity_diff);
+    for ((_, mut position, mut velocity, _, mut transform), (new_position, new_velocity)) in
+        query.iter_mut().zip(positions.into_iter().zip(velocities))
+    {
+        position.0 = new_position;
+        velocity.0 = new_velocity;
+        transform.translation = (view_scale.to_screen(position.0), transform.translation.z).into();
     }
 }
 
-fn calculate_new_state(
-    mut query: Query<
-        (
-            Entity,
-            &Name,
-            &mut Position,
-            &mut Velocity,
-            &Mass,
-            &mut Transform,
+fn merge_collided_bodies(
+    mut commands: Commands,
+    mut bodies_collided_event: EventWriter<BodiesCollided>,
+    mut query: Query<(
+        Entity,
+        &mut Name,
+        &mut Position,
+        &mut Velocity,
+        &mut Mass,
+        &mut Diameter,
+        Option<&TraceLine>,
+    )>,
+) {
+    let mut entities = vec![];
+    let mut states = vec![];
+
+    for (entity, _, position, velocity, mass, diameter, _) in query.iter_mut() {
+        entities.push(entity);
+        states.push(BodyState {
+            position: position.0,
+            velocity: velocity.0,
+            mass: mass.0,
+            diameter: diameter.0,
+        });
+    }
+
+    let collisions = merge_bodies(&mut states, || {
+        query
+            .iter_mut()
+            .map(|(_, name, ..)| name.0.clone())
+            .collect()
+    });
+    let mut merged_away = vec![false; entities.len()];
+
+    // the survivor keeps its trace, the trace of the removed body goes away with it
+    for collision in &collisions {
+        let removed_entity = entities[collision.removed];
+        if let Ok((.., Some(trace))) = query.get_mut(removed_entity) {
+            for point_entity in &trace.points {
+                commands.entity(*point_entity).despawn();
+            }
+        }
+        commands.entity(removed_entity).despawn();
+
+        bodies_collided_event.send(BodiesCollided {
+            first: collision.first.clone(),
+            second: collision.second.clone(),
+            merged: entities[collision.survivor],
+        });
+
+        merged_away[collision.removed] = true;
+    }
+
+    for collision in &collisions {
+        if merged_away[collision.survivor] {
+            continue;
+        }
+
+        let merged_state = &states[collision.survivor];
+        let (_, mut name, mut position, mut velocity, mut mass, mut diameter, _) =
+            query.get_mut(entities[collision.survivor]).unwrap();
+
+        // a survivor merged again gets the name of its last merge
+        name.0 = collision.merged_name();
+        position.0 = merged_state.position;
+        velocity.0 = merged_state.velocity;
+        mass.0 = merged_state.mass;
+        diameter.0 = merged_state.diameter;
+    }
+}
+
+fn log_collisions(mut bodies_collided_event: EventReader<BodiesCollided>, query: Query<&Name>) {
+    for event in bodies_collided_event.iter() {
+        let merged_name = query
+            .get(event.merged)
+            .map(|name| name.0.clone())
+            .unwrap_or_default();
+
+        info!(
+            "{} collided with {} and merged into {}",
+            event.first, event.second, merged_name
+        );
+    }
+}
+
+fn log_diverged_states(mut state_diverged_event: EventReader<StateDiverged>) {
+    for event in state_diverged_event.iter() {
+        error!(
+            "State of {} is not finite anymore, the simulation is paused",
+            event.name
+        );
+    }
+}
+
+fn tick_simulation_clock(time: Res<Time>, mut clock: ResMut<SimulationClock>) {
+    clock.tick(time.delta_seconds_f64());
+}
+
+fn physics_step_due(mut clock: ResMut<SimulationClock>) -> ShouldRun {
+    if clock.take_step() {
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn trace_interval_passed(clock: Res<SimulationClock>, mut last_sample: Local<f64>) -> ShouldRun {
+    if (clock.elapsed - *last_sample).abs() < clock.step * STEPS_PER_TRACE_POINT {
+        return ShouldRun::No;
+    }
+
+    *last_sample = clock.elapsed;
+
+    ShouldRun::Yes
+}
+
+/// Space pauses, `.` takes a single step, `+` and `-` change the warp, `R` reverses time
+fn control_simulation_clock(keys: Res<Input<KeyCode>>, mut clock: ResMut<SimulationClock>) {
+    let pressed = |codes: &[KeyCode]| codes.iter().any(|code| keys.just_pressed(*code));
+
+    if pressed(&[KeyCode::Space]) {
+        clock.toggle_pause();
+    } else if pressed(&[KeyCode::Period]) {
+        clock.single_step();
+    } else if pressed(&[KeyCode::Equals, KeyCode::NumpadAdd]) {
+        clock.speed_up();
+    } else if pressed(&[KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        clock.slow_down();
+    } else if pressed(&[KeyCode::R]) {
+        clock.reverse();
+    } else {
+        return;
+    }
+
+    info!(
+        "Simulation clock: {:.1} days, warp {:.2e}{}",
+        clock.elapsed / SECONDS_PER_DAY,
+        clock.warp,
+        if clock.paused { ", paused" } else { "" }
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
+fn save_snapshot(
+    keys: Res<Input<KeyCode>>,
+    clock: Res<SimulationClock>,
+    integrator: Res<Integrator>,
+    gravity: Res<Gravity>,
+    view_scale: Res<ViewScale>,
+    scenario: Res<Scenario>,
+    particles: Res<TestParticles>,
+    query: Query<BodyComponents>,
+    maneuvers_query: Query<&Maneuvers>,
+) {
+    if !keys.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    // in the order of the physics query, so that the forces are summed up the same way
+    let bodies = collect_bodies(&query);
+
+    let mut state = scenario.with_bodies(&bodies);
+    state.view_scale = Some(view_scale.0);
+    state.integrator = *integrator;
+    state.gravity = gravity.clone();
+    state.time_step = clock.step;
+    let flight_plans: Vec<FlightPlan> = maneuvers_query
+        .iter()
+        .map(|maneuvers| maneuvers.0.clone())
+        .collect();
+    state.set_flight_plans(&flight_plans);
+
+    let snapshot = Snapshot {
+        elapsed: clock.elapsed,
+        warp: Some(clock.warp),
+        scenario: state,
+        particles: particles.clone(),
+    };
+
+    match snapshot.save(SNAPSHOT_PATH) {
+        Ok(()) => info!(
+            "Saved the simulation at {:.1} days into {}",
+            clock.elapsed / SECONDS_PER_DAY,
+            SNAPSHOT_PATH
         ),
-        With<Mass>,
+        Err(error) => error!("{}: {}", SNAPSHOT_PATH, error),
+    }
+}
+
+/// Replaces all the bodies, their traces, orbits and the particles with the ones from the snapshot file
+#[allow(clippy::too_many_arguments)]
+fn load_snapshot(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut clock: ResMut<SimulationClock>,
+    mut integrator: ResMut<Integrator>,
+    mut gravity: ResMut<Gravity>,
+    mut view_scale: ResMut<ViewScale>,
+    mut scenario: ResMut<Scenario>,
+    mut precession_meter: ResMut<PrecessionMeter>,
+    mut frame: ResMut<KeptFrame>,
+    mut particles: ResMut<TestParticles>,
+    mut conservation: ResMut<ConservationMonitor>,
+    traces_toggle_query: Query<&ToggleState, With<ShowTracesToggle>>,
+    despawn_query: Query<
+        Entity,
+        Or<(
+            With<Mass>,
+            With<TracePoint>,
+            With<Orbit>,
+            With<ParticleCloud>,
+        )>,
     >,
-    view_scale: Res<ViewScale>,
 ) {
-    let mut prev_state = vec![];
+    if !keys.just_pressed(KeyCode::F9) {
+        return;
+    }
+
+    let snapshot = match Snapshot::load(SNAPSHOT_PATH) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            error!("{}: {}", SNAPSHOT_PATH, error);
+            return;
+        }
+    };
 
-    for (entity, name, position, _, mass, _) in query.iter_mut() {
-        prev_state.push((entity.id(), name.clone(), position.clone(), mass.clone()));
+    despawn_query.for_each(|entity| commands.entity(entity).despawn());
+
+    let time_step = snapshot.scenario.time_step;
+    let mut restored_clock = SimulationClock::new(
+        time_step,
+        snapshot.warp.unwrap_or(time_step * STEPS_PER_SECOND),
+    );
+    restored_clock.elapsed = snapshot.elapsed;
+    restored_clock.paused = clock.paused;
+    *clock = restored_clock;
+
+    *integrator = snapshot.scenario.integrator;
+    *gravity = snapshot.scenario.gravity.clone();
+    if let Some(scale) = snapshot.scenario.view_scale {
+        view_scale.0 = scale;
     }
+    precession_meter.measurement.reset();
+    conservation.reset();
 
-    for (entity, _, mut position, mut velocity, _, mut transform) in query.iter_mut() {
-        for (other_entity_id, _, other_position, other_mass) in &prev_state {
-            if &entity.id() == other_entity_id {
-                continue;
-            }
+    let bodies = snapshot.scenario.bodies();
+    if let Some(center) = CenterOfMass::of_bodies(&bodies) {
+        frame.0 = BarycentricFrame::following(&center, snapshot.elapsed);
+    }
+
+    let draw_traces = traces_toggle_query
+        .iter()
+        .next()
+        .is_some_and(|toggle| toggle.0);
+
+    for (body, scenario_body) in bodies.into_iter().zip(&snapshot.scenario.bodies) {
+        spawn_body(&mut commands, &view_scale, body, scenario_body, draw_traces);
+    }
+
+    *particles = snapshot.particles;
+    *scenario = snapshot.scenario;
+
+    info!(
+        "Loaded the simulation at {:.1} days from {}",
+        clock.elapsed / SECONDS_PER_DAY,
+        SNAPSHOT_PATH
+    );
+}
+
+fn record_trajectory(
+    clock: Res<SimulationClock>,
+    mut recording: ResMut<TrajectoryRecording>,
+    query: Query<BodyComponents>,
+) {
+    let recorder = match &mut recording.0 {
+        Some(recorder) if recorder.is_due(clock.elapsed) => recorder,
+        _ => return,
+    };
+
+    let bodies = collect_bodies(&query);
+
+    if let Err(error) = recorder.record(clock.elapsed, &bodies) {
+        error!("Recording stopped, can't write the trajectory: {}", error);
+        recording.0 = None;
+    }
+}
+
+/// Samples the energy, momentum and angular momentum, and warns when they drifted too far
+fn check_conservation(
+    clock: Res<SimulationClock>,
+    gravity: Res<Gravity>,
+    mut monitor: ResMut<ConservationMonitor>,
+    mut recording: ResMut<DiagnosticsRecording>,
+    query: Query<BodyComponents>,
+) {
+    let bodies = collect_bodies(&query);
+
+    for quantity in monitor.record(clock.elapsed, &bodies, &gravity) {
+        warn!(
+            "The {} drifted by more than {:e} after {:.1} days, the results may not be trustworthy",
+            quantity,
+            monitor.threshold,
+            clock.elapsed / SECONDS_PER_DAY
+        );
+    }
+
+    if let (Some(writer), Some(sample)) = (&mut recording.0, monitor.latest()) {
+        if let Err(error) = writer.write(sample) {
+            error!("Diagnostics stopped, can't write them: {}", error);
+            recording.0 = None;
+        }
+    }
+}
+
+/// Measures the drift from after the bodies were added, removed, moved together or merged
+fn rebase_conservation(
+    mut bodies_changed: EventReader<BodiesChanged>,
+    mut bodies_collided: EventReader<BodiesCollided>,
+    mut monitor: ResMut<ConservationMonitor>,
+) {
+    if bodies_changed.iter().count() + bodies_collided.iter().count() > 0 {
+        monitor.rebase();
+    }
+}
+
+/// Logs how far the conserved quantities drifted when D is pressed
+fn report_conservation(keys: Res<Input<KeyCode>>, monitor: Res<ConservationMonitor>) {
+    if !keys.just_pressed(KeyCode::D) {
+        return;
+    }
+
+    if let Some(sample) = monitor.latest() {
+        info!(
+            "Energy {:.6e} J (kinetic {:.6e}, potential {:.6e}), drift {:.2e}; \
+             momentum drift {:.2e}; angular momentum drift {:.2e}; {} samples",
+            sample.totals.energy(),
+            sample.totals.kinetic_energy,
+            sample.totals.potential_energy,
+            sample.energy_drift,
+            sample.momentum_drift,
+            sample.angular_momentum_drift,
+            monitor.samples().len()
+        );
+    }
+}
+
+fn update_delta_v_budget(query: Query<&Maneuvers>, mut budget: ResMut<DeltaVBudget>) {
+    let total = query
+        .iter()
+        .fold(DeltaVBudget::default(), |total, maneuvers| DeltaVBudget {
+            used: total.used + maneuvers.0.used_delta_v(),
+            planned: total.planned + maneuvers.0.planned_delta_v(),
+        });
+
+    // only on a change, so that the bar isn't updated every frame
+    if *budget != total {
+        *budget = total;
+    }
+}
 
-            // let force = G *  mass.0 * other_mass.0 / square_distance(&position, other_position);
-            let acceleration = G * other_mass.0 / position.0.distance_squared(other_position.0);
-            let angle = ZERO_ANGLE
-                .truncate()
-                .angle_between((other_position.0 - position.0).truncate());
-            let velocity_diff_length = acceleration * TIME_INTERVAL;
-            let velocity_diff = Vec3::new(
-                velocity_diff_length * angle.cos(),
-                velocity_diff_length * angle.sin(),
-                0.0,
+/// Logs the delta-v every spacecraft used of its maneuvers when B is pressed
+fn report_delta_v_budget(keys: Res<Input<KeyCode>>, query: Query<&Maneuvers>) {
+    if !keys.just_pressed(KeyCode::B) {
+        return;
+    }
+
+    for maneuvers in query.iter() {
+        let plan = &maneuvers.0;
+        let burned = plan
+            .maneuvers
+            .iter()
+            .filter(|maneuver| maneuver.burned.is_some())
+            .count();
+
+        info!(
+            "{}: used {:.1} of {:.1} m/s of delta-v, {} of {} maneuvers burned",
+            plan.spacecraft,
+            plan.used_delta_v(),
+            plan.planned_delta_v(),
+            burned,
+            plan.maneuvers.len()
+        );
+    }
+}
+
+fn report_step_sizes(integrator: Res<Integrator>, mut step_report: ResMut<StepReport>) {
+    if let Integrator::DormandPrince { .. } = *integrator {
+        if let (Some(min), Some(max)) = (step_report.min(), step_report.max()) {
+            info!(
+                "Accepted {} steps of {:.1}..{:.1} s, rejected {}",
+                step_report.accepted.len(),
+                min,
+                max,
+                step_report.rejected,
             );
-            velocity.0.add_assign(velocity_diff);
+        }
+    }
+
+    *step_report = StepReport::default();
+}
 
-            // println!(
-            //     "{} to {} acc: {:e}, angle: {:e}, velocity diff: ({})",
-            //     name.0, other_name.0, acceleration, angle, velocity_diff,
-            // );
+/// Compares the Barnes–Hut accelerations with the direct sum, which takes O(N²)
+fn report_force_error(
+    keys: Res<Input<KeyCode>>,
+    gravity: Res<Gravity>,
+    query: Query<(&Position, &Velocity, &Mass)>,
+) {
+    if !keys.just_pressed(KeyCode::F) {
+        return;
+    }
+
+    if let ForceSolver::BarnesHut { opening_angle } = gravity.solver {
+        let mut positions = vec![];
+        let mut velocities = vec![];
+        let mut masses = vec![];
+
+        for (position, velocity, mass) in query.iter() {
+            positions.push(position.0);
+            velocities.push(velocity.0);
+            masses.push(mass.0);
         }
-        position.0.add_assign(velocity.0 * TIME_INTERVAL);
-        transform.translation = (
-            position.0.mul(view_scale.0).truncate(),
-            transform.translation.z,
-        )
-            .into();
-        // println!("{} ({:?}) => [{:?}]", name.0, position.0, velocity.0);
+
+        let error =
+            barnes_hut::force_error(&gravity, &positions, &velocities, &masses, opening_angle);
+
+        info!(
+            "Barnes–Hut force error: max {:.2e}, mean {:.2e}",
+            error.max, error.mean
+        );
+    } else {
+        info!("The forces are summed directly, without any approximation");
     }
-    // println!("------");
 }
 
-fn setup(
-    mut commands: Commands,
-    view_scale: Res<ViewScale>,
-    mut adjust_sun_velocity_event: EventWriter<NeedToAdjustSunVelocity>,
+fn measure_precession(
+    clock: Res<SimulationClock>,
+    mut precession_meter: ResMut<PrecessionMeter>,
+    bodies_query: Query<(BodyComponents, &OrbitAround)>,
 ) {
+    let orbit = orbit_references(&bodies_query)
+        .into_iter()
+        .find(|(body, _)| body.name == precession_meter.body);
+
+    if let Some((body, Some(reference))) = orbit {
+        precession_meter.measurement.record(
+            clock.elapsed,
+            body.position - reference.position,
+            body.velocity - reference.velocity,
+            reference.sgp,
+        );
+
+        let samples = precession_meter.measurement.samples().len();
+        if samples % PRECESSION_REPORT_SAMPLES == 0 {
+            if let Some(rate) = precession_meter.measurement.arcseconds_per_century() {
+                info!(
+                    "{} periapsis precession: {:.1}″ per century ({} samples)",
+                    precession_meter.body, rate, samples
+                );
+            }
+        }
+    }
+}
+
+fn setup(mut commands: Commands, view_scale: Res<ViewScale>, scenario: Res<Scenario>) {
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(UiCameraBundle::default());
 
@@ -480,88 +1233,103 @@ fn setup(
         length: MAX_SCALE_LINE_LENGTH,
     });
 
-    commands = add_sun(commands, &view_scale);
-
-    let planets_data = [
-        (
-            Name("Mercury".to_string()),
-            Position(Vec3::new(69.817445e9, 0.0, 0.0)),
-            Velocity(Vec3::new(0.0, 38.7e3, 0.0)),
-            Mass(3.285e23),
-        ),
-        (
-            Name("Venus".to_string()),
-            Position(Vec3::new(-108e9, 0.0, 0.0)),
-            Velocity(Vec3::new(0.0, -35.0e3, 0.0)),
-            Mass(4.867e24),
-        ),
-        (
-            Name("Earth".to_string()),
-            Position(Vec3::new(0.0, 152.098232e9, 0.0)),
-            Velocity(Vec3::new(-29.4e3, 0.0, 0.0)),
-            Mass(5.9722e24),
-        ),
-        (
-            Name("Mars".to_string()),
-            Position(Vec3::new(0.0, -249.232e9, 0.0)),
-            Velocity(Vec3::new(22.0e3, 0.0, 0.0)),
-            Mass(6.4171e23),
-        ),
-        (
-            Name("Jupiter".to_string()),
-            Position(Vec3::new(816.5208e9, 0.0, 0.0)),
-            Velocity(Vec3::new(0.0, 12.0e3, 0.0)),
-            Mass(1.8986e27),
-        ),
-        (
-            Name("Saturn".to_string()),
-            Position(Vec3::new(0.0, 1513.325783e9, 0.0)),
-            Velocity(Vec3::new(-9.0e3, 0.0, 0.0)),
-            Mass(5.6846e26),
-        ),
-        (
-            Name("Uranus".to_string()),
-            Position(Vec3::new(-3004.419704e9, 0.0, 0.0)),
-            Velocity(Vec3::new(0.0, -6.0e3, 0.0)),
-            Mass(8.6813e25),
-        ),
-        (
-            Name("Neptune".to_string()),
-            Position(Vec3::new(0.0, -4553.946490e9, 0.0)),
-            Velocity(Vec3::new(5.4e3, 0.0, 0.0)),
-            Mass(8.6813e25),
-        ),
-    ];
+    for (body, scenario_body) in scenario.bodies().into_iter().zip(&scenario.bodies) {
+        spawn_body(&mut commands, &view_scale, body, scenario_body, false);
+    }
+}
 
-    let shape = shapes::Circle {
-        radius: 2.0,
-        center: Vec2::new(0.0, 0.0),
+fn spawn_body(
+    commands: &mut Commands,
+    view_scale: &ViewScale,
+    body: Body,
+    scenario_body: &ScenarioBody,
+    draw_trace: bool,
+) {
+    let scaled_position = view_scale.to_screen(body.position);
+    let trace = TraceLine {
+        draw: draw_trace,
+        ..Default::default()
     };
 
-    for (name, position, velocity, mass) in planets_data.into_iter() {
-        let scaled_position = position.0 * view_scale.0;
+    if scenario_body.star {
+        let star_circle = shapes::Circle {
+            radius: f32::max(MIN_STAR_SIZE, view_scale.length_to_screen(body.diameter)),
+            center: Vec2::new(0.0, 0.0),
+        };
+
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &star_circle,
+                // ShapeColors::new(Color::YELLOW),
+                DrawMode::Fill(FillMode::color(body_color(scenario_body, Color::YELLOW))),
+                Transform::from_xyz(scaled_position.x, scaled_position.y, 0.0),
+            ))
+            .insert(trace)
+            .insert(Star)
+            .insert(OrbitAround(scenario_body.draw_orbit_around.clone()))
+            .insert(Name(body.name))
+            .insert(ScenarioName(body.scenario_name))
+            .insert(Position(body.position))
+            .insert(Velocity(body.velocity))
+            .insert(Mass(body.mass))
+            .insert(Diameter(body.diameter));
+    } else if body.mass == 0.0 {
+        let shape = shapes::Polygon {
+            points: vec![
+                Vec2::new(0.0, SPACECRAFT_SIZE),
+                Vec2::new(-SPACECRAFT_SIZE, -SPACECRAFT_SIZE),
+                Vec2::new(SPACECRAFT_SIZE, -SPACECRAFT_SIZE),
+            ],
+            closed: true,
+        };
+        let flight_plan = FlightPlan::new(body.name.clone(), scenario_body.maneuvers.clone());
 
         commands
             .spawn_bundle(GeometryBuilder::build_as(
                 &shape,
-                DrawMode::Fill(FillMode::color(Color::BLACK)),
+                DrawMode::Fill(FillMode::color(body_color(scenario_body, Color::ORANGE))),
                 Transform::from_xyz(scaled_position.x, scaled_position.y, 50.0),
             ))
-            .insert(TraceLine::default())
+            .insert(trace)
+            .insert(Spacecraft)
+            .insert(Maneuvers(flight_plan))
+            .insert(OrbitAround(scenario_body.draw_orbit_around.clone()))
+            .insert(Name(body.name))
+            .insert(ScenarioName(body.scenario_name))
+            .insert(Position(body.position))
+            .insert(Velocity(body.velocity))
+            .insert(Mass(body.mass))
+            .insert(Diameter(body.diameter));
+    } else {
+        let shape = shapes::Circle {
+            radius: 2.0,
+            center: Vec2::new(0.0, 0.0),
+        };
+
+        commands
+            .spawn_bundle(GeometryBuilder::build_as(
+                &shape,
+                DrawMode::Fill(FillMode::color(body_color(scenario_body, Color::BLACK))),
+                Transform::from_xyz(scaled_position.x, scaled_position.y, 50.0),
+            ))
+            .insert(trace)
             .insert(Planet)
-            .insert(name)
-            .insert(position)
-            .insert(velocity.clone())
-            .insert(mass.clone());
-
-        adjust_sun_velocity_event.send(NeedToAdjustSunVelocity {
-            change: SystemChanged::BodyAdded,
-            velocity,
-            mass,
-        });
+            .insert(OrbitAround(scenario_body.draw_orbit_around.clone()))
+            .insert(Name(body.name))
+            .insert(ScenarioName(body.scenario_name))
+            .insert(Position(body.position))
+            .insert(Velocity(body.velocity))
+            .insert(Mass(body.mass))
+            .insert(Diameter(body.diameter));
     }
 }
 
+fn body_color(body: &ScenarioBody, default: Color) -> Color {
+    body.color
+        .map(|[red, green, blue]| Color::rgb(red, green, blue))
+        .unwrap_or(default)
+}
+
 fn draw_trace_point(
     mut commands: Commands,
     view_scale: Res<ViewScale>,
@@ -577,7 +1345,7 @@ fn draw_trace_point(
             continue;
         }
 
-        let scaled = position.0.mul(view_scale.0);
+        let scaled = view_scale.to_screen(position.0);
 
         let point_entity = commands
             .spawn_bundle(GeometryBuilder::build_as(
@@ -613,107 +1381,266 @@ fn on_new_trace_point(
     }
 }
 
-fn add_sun<'w, 's>(mut commands: Commands<'w, 's>, view_scale: &ViewScale) -> Commands<'w, 's> {
-    let sun_position = Position(Vec3::new(0.0, 0.0, 0.0));
-    let sun_diameter = 1.39268e9;
+fn add_stars<'w, 's>(
+    mut commands: Commands<'w, 's>,
+    view_scale: &ViewScale,
+    scenario: &Scenario,
+) -> Commands<'w, 's> {
+    for (star, scenario_body) in scenario.bodies().into_iter().zip(&scenario.bodies) {
+        if scenario_body.star {
+            spawn_body(&mut commands, view_scale, star, scenario_body, false);
+        }
+    }
 
-    let sun_circle = shapes::Circle {
-        radius: f32::max(MIN_STAR_SIZE, sun_diameter * view_scale.0),
-        center: Vec2::new(0.0, 0.0),
-    };
+    commands
+}
+
+/// All the test particles, drawn as a single shape because there are thousands of them
+#[derive(Component)]
+struct ParticleCloud;
+
+fn draw_particles(
+    mut commands: Commands,
+    view_scale: Res<ViewScale>,
+    clock: Res<SimulationClock>,
+    particles: Res<TestParticles>,
+    mut last_drawn: Local<f64>,
+    query: Query<Entity, With<ParticleCloud>>,
+) {
+    let redraw_due = (clock.elapsed - *last_drawn).abs() >= clock.step * STEPS_PER_PARTICLE_DRAW;
+
+    if !view_scale.is_changed() && !(particles.is_changed() && redraw_due) {
+        return;
+    }
+
+    *last_drawn = clock.elapsed;
+
+    query.for_each(|entity| commands.entity(entity).despawn());
+
+    if particles.is_empty() {
+        return;
+    }
+
+    let mut builder = GeometryBuilder::new();
+    for position in &particles.positions {
+        builder.add(&shapes::Circle {
+            radius: PARTICLE_SIZE,
+            center: view_scale.to_screen(*position),
+        });
+    }
 
     commands
-        .spawn_bundle(GeometryBuilder::build_as(
-            &sun_circle,
-            // ShapeColors::new(Color::YELLOW),
-            DrawMode::Fill(FillMode::color(Color::YELLOW)),
+        .spawn_bundle(builder.build(
+            DrawMode::Fill(FillMode::color(Color::GRAY)),
             Transform::default(),
         ))
-        .insert(TraceLine::default())
-        .insert(Star)
-        .insert(Name("Sun".to_string()))
-        .insert(sun_position.clone())
-        .insert(Velocity(Vec3::new(0.0, 0.0, 0.0)))
-        .insert(Mass(1.989e30))
-        .insert(Diameter(sun_diameter));
-
-    commands
+        .insert(ParticleCloud);
 }
 
 #[derive(Component)]
 struct Orbit;
 
+/// What the orbit of a body is drawn around
+#[derive(Component)]
+struct OrbitAround(OrbitPrimary);
+
+/// Components of a body entity that make up its state
+type BodyComponents = (
+    &'static Name,
+    &'static ScenarioName,
+    &'static Position,
+    &'static Velocity,
+    &'static Mass,
+    &'static Diameter,
+);
+
+/// State of a body entity, as the simulation library sees it
+fn body_of(
+    (name, scenario_name, position, velocity, mass, diameter): (
+        &Name,
+        &ScenarioName,
+        &Position,
+        &Velocity,
+        &Mass,
+        &Diameter,
+    ),
+) -> Body {
+    Body {
+        name: name.0.clone(),
+        scenario_name: scenario_name.0.clone(),
+        position: position.0,
+        velocity: velocity.0,
+        mass: mass.0,
+        diameter: diameter.0,
+    }
+}
+
+/// Every body, in the order of the query
+fn collect_bodies(query: &Query<BodyComponents>) -> Vec<Body> {
+    query.iter().map(body_of).collect()
+}
+
+/// Every body with the point its orbit is computed around, when it has one
+fn orbit_references(
+    query: &Query<(BodyComponents, &OrbitAround)>,
+) -> Vec<(Body, Option<Reference>)> {
+    let (bodies, primaries): (Vec<Body>, Vec<OrbitPrimary>) = query
+        .iter()
+        .map(|(components, orbit_around)| (body_of(components), orbit_around.0.clone()))
+        .unzip();
+
+    let references = orbit::references(&bodies, &primaries);
+
+    bodies.into_iter().zip(references).collect()
+}
+
 fn set_baricenters(
     mut commands: Commands,
     view_scale: Res<ViewScale>,
-    mut planets_query: Query<(&Name, &Position, &Velocity, &Mass), (With<Planet>, Without<Star>)>,
-    sun_query: Query<(&Position, &Velocity, &Mass), With<Star>>,
+    bodies_query: Query<(BodyComponents, &OrbitAround)>,
     orbits_query: Query<Entity, With<Orbit>>,
 ) {
-    if sun_query.is_empty() {
-        return;
-    }
-
     orbits_query.for_each(|orbit| commands.entity(orbit).despawn());
 
-    let (sun_position, sun_velocity, sun_mass) = sun_query.single();
-
-    for (_, planet_position, planet_velocity, planet_mass) in planets_query.iter_mut() {
-        let distance_vector = planet_position - sun_position;
-
-        let baricenter = distance_vector * (planet_mass / &(sun_mass + planet_mass)) + sun_position;
+    for (body, reference) in orbit_references(&bodies_query) {
+        if let Some(reference) = reference {
+            let elements = reference.elements_of(&body);
+            let distance = body.position.distance(reference.position);
+
+            spawn_orbit(
+                &mut commands,
+                &view_scale,
+                reference.position,
+                &elements,
+                distance,
+            );
+        }
+    }
+}
 
-        let position_vector = planet_position - &baricenter;
-        let velocity_vector = planet_velocity - sun_velocity;
-        let distance = position_vector.0.length();
-        let velocity_squared = velocity_vector.0.length_squared();
+/// Draws the orbit of a body that is `distance` away from its primary at `primary_position`.
+/// Closed orbits are drawn whole, open ones around the periapsis up to a few times farther
+/// than the body, with their asymptotes and the periapsis marked
+fn spawn_orbit(
+    commands: &mut Commands,
+    view_scale: &ViewScale,
+    primary_position: DVec3,
+    elements: &Elements,
+    distance: f64,
+) {
+    let to_screen =
+        |true_anomaly| view_scale.to_screen(primary_position + elements.position_at(true_anomaly));
+
+    let escape_anomaly = match elements.escape_anomaly() {
+        Some(escape_anomaly) => escape_anomaly,
+        None => {
+            let points = (0..ORBIT_POINTS)
+                .map(|point| to_screen(TAU * point as f64 / ORBIT_POINTS as f64))
+                .collect();
+
+            commands
+                .spawn_bundle(GeometryBuilder::build_as(
+                    &shapes::Polygon {
+                        points,
+                        closed: true,
+                    },
+                    DrawMode::Stroke(StrokeMode::color(Color::INDIGO)),
+                    Transform::default(),
+                ))
+                .insert(Orbit);
+
+            return;
+        }
+    };
 
-        let semi_major_axis_length =
-            SUN_SGP * distance / (2.0 * SUN_SGP - distance * velocity_squared);
+    let extent = OPEN_ORBIT_EXTENT * distance.max(elements.periapsis());
+    let max_anomaly = elements
+        .true_anomaly_at_distance(extent)
+        .unwrap_or(escape_anomaly);
 
-        let h = position_vector.0.cross(velocity_vector.0); // specific angular momentum
+    let points = (0..=ORBIT_POINTS)
+        .map(|point| to_screen(max_anomaly * (2.0 * point as f64 / ORBIT_POINTS as f64 - 1.0)))
+        .collect();
 
-        let eccentricity_vector =
-            position_vector.0 / distance - (velocity_vector.0.cross(h) / SUN_SGP);
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &shapes::Polygon {
+                points,
+                closed: false,
+            },
+            DrawMode::Stroke(StrokeMode::color(Color::INDIGO)),
+            Transform::default(),
+        ))
+        .insert(Orbit);
+
+    // a parabola has no asymptotes, its arms end up parallel. So does a hyperbola too close
+    // to it for its semi-major axis to be finite
+    if elements.eccentricity > 1.0 && elements.semi_major_axis.is_finite() {
+        let center = primary_position + elements.center();
+
+        for direction in [escape_anomaly, -escape_anomaly] {
+            let (sin, cos) = direction.sin_cos();
+            let end = center + elements.orientation() * DVec3::new(cos, sin, 0.0) * extent;
+
+            commands
+                .spawn_bundle(GeometryBuilder::build_as(
+                    &shapes::Line(view_scale.to_screen(center), view_scale.to_screen(end)),
+                    DrawMode::Stroke(StrokeMode::color(Color::rgba(0.29, 0.0, 0.51, 0.4))),
+                    Transform::default(),
+                ))
+                .insert(Orbit);
+        }
+    }
 
-        let semi_minor_axis_length =
-            semi_major_axis_length * (1.0 - eccentricity_vector.length_squared()).sqrt();
+    commands
+        .spawn_bundle(GeometryBuilder::build_as(
+            &shapes::Circle {
+                radius: PERIAPSIS_MARKER_SIZE,
+                center: to_screen(0.0),
+            },
+            DrawMode::Fill(FillMode::color(Color::INDIGO)),
+            Transform::default(),
+        ))
+        .insert(Orbit);
+}
 
-        let empty_focus = 2.0 * semi_major_axis_length * eccentricity_vector;
+/// Logs the orbit of every body when O is pressed
+fn report_orbits(keys: Res<Input<KeyCode>>, bodies_query: Query<(BodyComponents, &OrbitAround)>) {
+    if !keys.just_pressed(KeyCode::O) {
+        return;
+    }
 
-        let center = (empty_focus - baricenter.0) / 2.0;
+    for (body, reference) in orbit_references(&bodies_query) {
+        let reference = match reference {
+            Some(reference) => reference,
+            None => continue,
+        };
+        let elements = reference.elements_of(&body);
 
-        let ellipsis_angle = ZERO_ANGLE
-            .truncate()
-            .angle_between(eccentricity_vector.truncate());
+        let period = match elements.period(reference.sgp) {
+            Some(period) => format!("{:.2} days", period / SECONDS_PER_DAY),
+            None => "escaping".to_string(),
+        };
+        let apoapsis = match elements.apoapsis() {
+            Some(apoapsis) => format!("{:.4} AU", apoapsis / METERS_PER_AU),
+            None => "none".to_string(),
+        };
 
-        let mut transform = Transform::from_translation(center * view_scale.0);
-        transform.rotate(Quat::from_rotation_z(ellipsis_angle));
-        commands
-            .spawn_bundle(GeometryBuilder::build_as(
-                &shapes::Ellipse {
-                    center: Vec2::default(),
-                    radii: Vec2::new(
-                        semi_major_axis_length * view_scale.0,
-                        semi_minor_axis_length * view_scale.0,
-                    ),
-                },
-                DrawMode::Stroke(StrokeMode::color(Color::INDIGO)),
-                transform,
-            ))
-            .insert(Orbit);
-
-        // commands.spawn_bundle(GeometryBuilder::build_as(
-        //     &shapes::Circle {
-        //         radius: 2.0,
-        //         center: Vec2::new(0.0, 0.0),
-        //     },
-        //     DrawMode::Fill(FillMode::color(Color::INDIGO)),
-        //     Transform::from_xyz(
-        //         empty_focus.x * view_scale.0,
-        //         empty_focus.y * view_scale.0,
-        //         10.0,
-        //     ),
-        // ));
+        info!(
+            "{} around {}: a {:.4} AU, e {:.4}, i {:.3}°, Ω {:.3}°, ω {:.3}°, ν {:.3}°, M {:.3}°, \
+             period {}, periapsis {:.4} AU, apoapsis {}",
+            body.name,
+            reference.name,
+            elements.semi_major_axis / METERS_PER_AU,
+            elements.eccentricity,
+            elements.inclination.to_degrees(),
+            elements.longitude_of_ascending_node.to_degrees(),
+            elements.argument_of_periapsis.to_degrees(),
+            elements.true_anomaly.to_degrees(),
+            elements.mean_anomaly().to_degrees(),
+            period,
+            elements.periapsis() / METERS_PER_AU,
+            apoapsis
+        );
     }
 }
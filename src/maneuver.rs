@@ -0,0 +1,303 @@
+use bevy_math::DVec3;
+use serde::{Deserialize, Serialize};
+
+/// Impulsive burn of a spacecraft: its velocity changes at once at `time`.
+/// The directions are those of its orbit around `relative_to`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Maneuver {
+    /// Simulated seconds since the start
+    pub time: f64,
+    /// Name of the body the burn directions are taken relative to
+    pub relative_to: String,
+    /// Meters per second along the velocity relative to the body, negative to brake
+    #[serde(default)]
+    pub prograde: f64,
+    /// Meters per second along the angular momentum around the body, out of the orbital plane
+    #[serde(default)]
+    pub normal: f64,
+    /// Meters per second away from the body, in the orbital plane and across the velocity
+    #[serde(default)]
+    pub radial: f64,
+    /// Velocity change the burn made, set once it's burned. Integrating backwards over the burn
+    /// takes it back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burned: Option<DVec3>,
+}
+
+impl Maneuver {
+    /// Meters per second the burn costs
+    pub fn delta_v(&self) -> f64 {
+        DVec3::new(self.prograde, self.normal, self.radial).length()
+    }
+
+    /// Velocity change of the burn for a spacecraft at `relative_position` moving with
+    /// `relative_velocity` around the reference body. A component whose direction isn't
+    /// defined, like the normal one of a spacecraft falling straight down, is left out
+    pub fn velocity_change(&self, relative_position: DVec3, relative_velocity: DVec3) -> DVec3 {
+        let prograde = relative_velocity.normalize_or_zero();
+        let normal = relative_position
+            .cross(relative_velocity)
+            .normalize_or_zero();
+        let radial = prograde.cross(normal);
+
+        prograde * self.prograde + normal * self.normal + radial * self.radial
+    }
+}
+
+/// Maneuver nodes of a spacecraft, in the order of their times
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlightPlan {
+    pub spacecraft: String,
+    pub maneuvers: Vec<Maneuver>,
+}
+
+impl FlightPlan {
+    pub fn new(spacecraft: String, mut maneuvers: Vec<Maneuver>) -> Self {
+        maneuvers.sort_by(|first, second| first.time.total_cmp(&second.time));
+
+        Self {
+            spacecraft,
+            maneuvers,
+        }
+    }
+
+    /// Meters per second of all the maneuvers
+    pub fn planned_delta_v(&self) -> f64 {
+        self.maneuvers.iter().map(Maneuver::delta_v).sum()
+    }
+
+    /// Meters per second of the maneuvers burned so far
+    pub fn used_delta_v(&self) -> f64 {
+        self.maneuvers
+            .iter()
+            .filter_map(|maneuver| maneuver.burned)
+            .map(DVec3::length)
+            .sum()
+    }
+}
+
+/// A maneuver burned, or taken back when integrating backwards
+#[derive(Debug)]
+pub struct Burn {
+    pub spacecraft: String,
+    /// Simulated seconds since the start
+    pub time: f64,
+    /// Velocity change, opposite to the one of the maneuver when it's taken back
+    pub velocity_change: DVec3,
+}
+
+/// Integrates over `dt` seconds from `time` with `step`, stopping at every maneuver of `plans`
+/// on the way to burn it at its exact time. The states are those of the bodies named `names`.
+/// Going backwards, the burns crossed are taken back.
+///
+/// A maneuver is burned once the time reaches it, maneuvers relative to a body that isn't there
+/// anymore are burned without any velocity change. Returns the burns in the order they were made
+pub fn step_with_maneuvers(
+    plans: &mut [FlightPlan],
+    names: &[String],
+    time: f64,
+    dt: f64,
+    positions: &mut [DVec3],
+    velocities: &mut [DVec3],
+    mut step: impl FnMut(&mut [DVec3], &mut [DVec3], f64),
+) -> Vec<Burn> {
+    let end = time + dt;
+    let forwards = dt >= 0.0;
+    let index_of = |name: &str| names.iter().position(|known| known == name);
+
+    // (time, plan, maneuver) of every maneuver crossed
+    let mut due = vec![];
+    for (plan_index, plan) in plans.iter().enumerate() {
+        if index_of(&plan.spacecraft).is_none() {
+            continue;
+        }
+
+        for (maneuver_index, maneuver) in plan.maneuvers.iter().enumerate() {
+            let crossed = if forwards {
+                maneuver.burned.is_none() && maneuver.time <= end
+            } else {
+                maneuver.burned.is_some() && maneuver.time > end
+            };
+
+            if crossed {
+                due.push((maneuver.time, plan_index, maneuver_index));
+            }
+        }
+    }
+
+    due.sort_by(|first, second| first.0.total_cmp(&second.0));
+    if !forwards {
+        due.reverse();
+    }
+
+    let mut current = time;
+    let mut burns = vec![];
+
+    for (burn_time, plan_index, maneuver_index) in due {
+        // maneuvers planned before the start of the step are burned right away
+        let substep = if forwards {
+            (burn_time - current).max(0.0)
+        } else {
+            (burn_time - current).min(0.0)
+        };
+        if substep != 0.0 {
+            step(positions, velocities, substep);
+            current += substep;
+        }
+
+        let plan = &mut plans[plan_index];
+        let spacecraft = index_of(&plan.spacecraft).expect("spacecraft of due maneuvers exist");
+        let maneuver = &mut plan.maneuvers[maneuver_index];
+
+        let velocity_change = if forwards {
+            let change = match index_of(&maneuver.relative_to) {
+                Some(reference) => maneuver.velocity_change(
+                    positions[spacecraft] - positions[reference],
+                    velocities[spacecraft] - velocities[reference],
+                ),
+                None => DVec3::ZERO,
+            };
+            maneuver.burned = Some(change);
+
+            change
+        } else {
+            -maneuver.burned.take().unwrap_or_default()
+        };
+
+        velocities[spacecraft] += velocity_change;
+        burns.push(Burn {
+            spacecraft: plan.spacecraft.clone(),
+            time: burn_time,
+            velocity_change,
+        });
+    }
+
+    if end != current {
+        step(positions, velocities, end - current);
+    }
+
+    burns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A probe next to a planet at rest, moving across the line between them
+    fn probe() -> (Vec<String>, Vec<DVec3>, Vec<DVec3>, Vec<FlightPlan>) {
+        let maneuver = Maneuver {
+            time: 3.0,
+            relative_to: "Planet".to_string(),
+            prograde: 2.0,
+            normal: 0.0,
+            radial: 0.0,
+            burned: None,
+        };
+
+        (
+            vec!["Planet".to_string(), "Probe".to_string()],
+            vec![DVec3::ZERO, DVec3::X],
+            vec![DVec3::ZERO, DVec3::Y],
+            vec![FlightPlan::new("Probe".to_string(), vec![maneuver])],
+        )
+    }
+
+    /// Steps without any forces, remembering the sizes of the steps
+    fn drift<'a>(steps: &'a mut Vec<f64>) -> impl FnMut(&mut [DVec3], &mut [DVec3], f64) + 'a {
+        move |positions, velocities, dt| {
+            for (position, velocity) in positions.iter_mut().zip(velocities.iter()) {
+                *position += *velocity * dt;
+            }
+            steps.push(dt);
+        }
+    }
+
+    #[test]
+    fn burns_at_the_maneuver_time() {
+        let (names, mut positions, mut velocities, mut plans) = probe();
+        let mut steps = vec![];
+
+        let burns = step_with_maneuvers(
+            &mut plans,
+            &names,
+            0.0,
+            10.0,
+            &mut positions,
+            &mut velocities,
+            drift(&mut steps),
+        );
+
+        assert_eq!(steps, vec![3.0, 7.0]);
+        assert_eq!(burns.len(), 1);
+        assert_eq!(burns[0].time, 3.0);
+        assert_eq!(burns[0].velocity_change, DVec3::new(0.0, 2.0, 0.0));
+        assert_eq!(positions[1], DVec3::new(1.0, 24.0, 0.0));
+        assert_eq!(plans[0].used_delta_v(), 2.0);
+
+        // burned only once
+        let burns = step_with_maneuvers(
+            &mut plans,
+            &names,
+            10.0,
+            10.0,
+            &mut positions,
+            &mut velocities,
+            drift(&mut steps),
+        );
+        assert!(burns.is_empty());
+        assert_eq!(velocities[1], DVec3::new(0.0, 3.0, 0.0));
+    }
+
+    #[test]
+    fn integrating_backwards_takes_the_burn_back() {
+        let (names, mut positions, mut velocities, mut plans) = probe();
+        let mut steps = vec![];
+
+        step_with_maneuvers(
+            &mut plans,
+            &names,
+            0.0,
+            10.0,
+            &mut positions,
+            &mut velocities,
+            drift(&mut steps),
+        );
+        steps.clear();
+        let burns = step_with_maneuvers(
+            &mut plans,
+            &names,
+            10.0,
+            -10.0,
+            &mut positions,
+            &mut velocities,
+            drift(&mut steps),
+        );
+
+        assert_eq!(steps, vec![-7.0, -3.0]);
+        assert_eq!(burns[0].velocity_change, DVec3::new(0.0, -2.0, 0.0));
+        assert_eq!(positions, probe().1);
+        assert_eq!(velocities, probe().2);
+        assert_eq!(plans, probe().3);
+    }
+
+    #[test]
+    fn maneuvers_relative_to_a_missing_body_change_nothing() {
+        let (_, mut positions, mut velocities, mut plans) = probe();
+        let names = vec!["Moon".to_string(), "Probe".to_string()];
+        let mut steps = vec![];
+
+        let burns = step_with_maneuvers(
+            &mut plans,
+            &names,
+            0.0,
+            10.0,
+            &mut positions,
+            &mut velocities,
+            drift(&mut steps),
+        );
+
+        assert_eq!(burns[0].velocity_change, DVec3::ZERO);
+        assert_eq!(plans[0].maneuvers[0].burned, Some(DVec3::ZERO));
+        assert_eq!(velocities[1], DVec3::Y);
+    }
+}
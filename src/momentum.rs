@@ -0,0 +1,98 @@
+use bevy_math::DVec3;
+
+use crate::simulation::Body;
+
+/// Where the mass of a system is on average and how it moves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CenterOfMass {
+    pub position: DVec3,
+    pub velocity: DVec3,
+    pub mass: f64,
+}
+
+impl CenterOfMass {
+    /// Center of the position, velocity and mass of every body. `None` when there's no mass
+    pub fn of(states: impl IntoIterator<Item = (DVec3, DVec3, f64)>) -> Option<Self> {
+        let (weighted_position, momentum, mass) = states.into_iter().fold(
+            (DVec3::ZERO, DVec3::ZERO, 0.0),
+            |(weighted_position, momentum, total_mass), (position, velocity, mass)| {
+                (
+                    weighted_position + position * mass,
+                    momentum + velocity * mass,
+                    total_mass + mass,
+                )
+            },
+        );
+
+        if mass > 0.0 {
+            Some(Self {
+                position: weighted_position / mass,
+                velocity: momentum / mass,
+                mass,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn of_bodies(bodies: &[Body]) -> Option<Self> {
+        Self::of(
+            bodies
+                .iter()
+                .map(|body| (body.position, body.velocity, body.mass)),
+        )
+    }
+
+    /// Total momentum of the system, kg·m/s
+    pub fn momentum(&self) -> DVec3 {
+        self.velocity * self.mass
+    }
+}
+
+/// Frame the center of mass is kept still in: it's at `position` at the time zero and moves
+/// at `velocity`. The default frame keeps it at the origin with no momentum
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BarycentricFrame {
+    pub position: DVec3,
+    pub velocity: DVec3,
+}
+
+impl BarycentricFrame {
+    /// Frame the center of mass already follows at `time`
+    pub fn following(center: &CenterOfMass, time: f64) -> Self {
+        Self {
+            position: center.position - center.velocity * time,
+            velocity: center.velocity,
+        }
+    }
+
+    /// Where the frame keeps the center of mass at `time`
+    pub fn position_at(&self, time: f64) -> DVec3 {
+        self.position + self.velocity * time
+    }
+
+    /// Shift of the positions and velocities that puts `center` back where the frame wants it
+    /// at `time`. Moving every body the same way keeps the orbits as they are
+    pub fn correction(&self, center: &CenterOfMass, time: f64) -> (DVec3, DVec3) {
+        (
+            self.position_at(time) - center.position,
+            self.velocity - center.velocity,
+        )
+    }
+
+    /// Moves `bodies` so that their center of mass is where the frame wants it at `time`.
+    /// Returns the position and velocity they were moved by
+    pub fn recenter(&self, bodies: &mut [Body], time: f64) -> (DVec3, DVec3) {
+        let (position_shift, velocity_shift) = match CenterOfMass::of_bodies(bodies) {
+            Some(center) => self.correction(&center, time),
+            None => return (DVec3::ZERO, DVec3::ZERO),
+        };
+
+        for body in bodies {
+            body.position += position_shift;
+            body.velocity += velocity_shift;
+        }
+
+        (position_shift, velocity_shift)
+    }
+}
@@ -0,0 +1,556 @@
+use std::f64::consts::{PI, TAU};
+
+use bevy_math::{DMat3, DVec3};
+use serde::{Deserialize, Serialize};
+
+use crate::{gravity::G, momentum::CenterOfMass, simulation::Body};
+
+/// Astronomical unit, meters
+pub const METERS_PER_AU: f64 = 149_597_870_700.0;
+pub const SECONDS_PER_DAY: f64 = 86400.0;
+
+/// Standard gravitational parameter `G * (M + m)` of a body of `mass` and its primary,
+/// the one its relative orbit follows
+pub fn gravitational_parameter(primary_mass: f64, mass: f64) -> f64 {
+    G * (primary_mass + mass)
+}
+
+/// Gravitational parameter of the orbit of a body of `mass` around the barycenter it shares
+/// with a body of `other_mass`: `G * M³ / (M + m)²`
+pub fn barycentric_gravitational_parameter(other_mass: f64, mass: f64) -> f64 {
+    let total_mass = other_mass + mass;
+
+    G * other_mass.powi(3) / (total_mass * total_mass)
+}
+
+/// A body and its strongest primary orbit their common barycenter when the primary
+/// is less than this many times heavier, like binary stars or Pluto and Charon
+const BINARY_MASS_RATIO: f64 = 10.0;
+
+/// What the orbit of a body is drawn around
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum OrbitPrimary {
+    /// The body whose sphere of influence it's deepest in, or their barycenter when
+    /// they have comparable masses
+    #[default]
+    Strongest,
+    /// The body of this name, the strongest one when it's gone
+    Body(String),
+    /// The center of mass of all the bodies
+    Barycenter,
+}
+
+/// The point an orbit is computed around
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    /// Name of the body or the barycenter, for display
+    pub name: String,
+    pub position: DVec3,
+    pub velocity: DVec3,
+    /// Gravitational parameter of the orbit around this point
+    pub sgp: f64,
+}
+
+impl Reference {
+    /// Osculating elements of `body` around this point
+    pub fn elements_of(&self, body: &Body) -> Elements {
+        Elements::from_state(
+            body.position - self.position,
+            body.velocity - self.velocity,
+            self.sgp,
+        )
+    }
+
+    fn of_body(primary: &Body, mass: f64) -> Self {
+        Self {
+            name: primary.name.clone(),
+            position: primary.position,
+            velocity: primary.velocity,
+            sgp: gravitational_parameter(primary.mass, mass),
+        }
+    }
+
+    /// Center of mass of `bodies`, which include the body of `mass` the orbit is of
+    fn barycenter<'a>(
+        name: String,
+        bodies: impl Iterator<Item = &'a Body>,
+        mass: f64,
+    ) -> Option<Self> {
+        let center = center_of_mass(bodies)?;
+
+        Some(Self::at_center(
+            name,
+            &center,
+            barycentric_gravitational_parameter(center.mass - mass, mass),
+        ))
+    }
+
+    /// Center of mass of `bodies` the body of `mass` goes around, as if they were a single body
+    fn outer_barycenter<'a>(
+        name: String,
+        bodies: impl Iterator<Item = &'a Body>,
+        mass: f64,
+    ) -> Option<Self> {
+        let center = center_of_mass(bodies)?;
+
+        Some(Self::at_center(
+            name,
+            &center,
+            gravitational_parameter(center.mass, mass),
+        ))
+    }
+
+    fn at_center(name: String, center: &CenterOfMass, sgp: f64) -> Self {
+        Self {
+            name,
+            position: center.position,
+            velocity: center.velocity,
+            sgp,
+        }
+    }
+}
+
+fn center_of_mass<'a>(bodies: impl Iterator<Item = &'a Body>) -> Option<CenterOfMass> {
+    CenterOfMass::of(bodies.map(|body| (body.position, body.velocity, body.mass)))
+}
+
+/// Point the orbit of every body is drawn around, given what each asks for in `primaries`.
+/// `None` for the body at the top of the hierarchy, which orbits nothing
+pub fn references(bodies: &[Body], primaries: &[OrbitPrimary]) -> Vec<Option<Reference>> {
+    let dominant = dominant_primaries(bodies);
+
+    // the lighter body of a binary pair, for every body heading one. The closest one
+    // when there are several, the others go around the pair
+    let partners: Vec<Option<usize>> = (0..bodies.len())
+        .map(|index| {
+            let distance = |other: usize| bodies[other].position.distance(bodies[index].position);
+
+            (0..bodies.len())
+                .filter(|&other| {
+                    dominant[other] == Some(index) && is_binary(&bodies[index], &bodies[other])
+                })
+                .min_by(|&first, &second| distance(first).total_cmp(&distance(second)))
+        })
+        .collect();
+
+    let pair_name = |first: usize, second: usize| {
+        format!(
+            "barycenter of {} and {}",
+            bodies[first].name, bodies[second].name
+        )
+    };
+    let pair_barycenter = |first: usize, second: usize, mass: f64| {
+        Reference::barycenter(
+            pair_name(first, second),
+            [&bodies[first], &bodies[second]].into_iter(),
+            mass,
+        )
+    };
+
+    // the other star of the binary pair `index` is part of
+    let companion = |index: usize| {
+        partners[index].or_else(|| {
+            dominant[index].filter(|&heavier| is_binary(&bodies[heavier], &bodies[index]))
+        })
+    };
+
+    let strongest = |index: usize| {
+        let body = &bodies[index];
+
+        match dominant[index] {
+            Some(primary) => {
+                // farther from its star than the companion is: a circumbinary orbit. Bodies
+                // in the sphere of the lighter star are closer, so `primary` is the heavier one
+                let circumbinary = companion(primary).filter(|&companion| {
+                    companion != index
+                        && body.position.distance(bodies[primary].position)
+                            > bodies[companion]
+                                .position
+                                .distance(bodies[primary].position)
+                });
+
+                match circumbinary {
+                    Some(companion) => Reference::outer_barycenter(
+                        pair_name(primary, companion),
+                        [&bodies[primary], &bodies[companion]].into_iter(),
+                        body.mass,
+                    ),
+                    None if is_binary(&bodies[primary], body) => {
+                        pair_barycenter(primary, index, body.mass)
+                    }
+                    None => Some(Reference::of_body(&bodies[primary], body.mass)),
+                }
+            }
+            None => partners[index].and_then(|partner| pair_barycenter(index, partner, body.mass)),
+        }
+    };
+
+    bodies
+        .iter()
+        .zip(primaries)
+        .enumerate()
+        .map(|(index, (body, primary))| match primary {
+            OrbitPrimary::Strongest => strongest(index),
+            OrbitPrimary::Body(name) => match bodies.iter().find(|other| &other.name == name) {
+                Some(other) if other.name != body.name => {
+                    Some(Reference::of_body(other, body.mass))
+                }
+                _ => strongest(index),
+            },
+            OrbitPrimary::Barycenter if bodies.len() > 1 => {
+                Reference::barycenter("barycenter".to_string(), bodies.iter(), body.mass)
+            }
+            OrbitPrimary::Barycenter => None,
+        })
+        .collect()
+}
+
+/// Whether `body` and its `primary` orbit each other as a pair rather than one the other
+fn is_binary(primary: &Body, body: &Body) -> bool {
+    primary.mass < BINARY_MASS_RATIO * body.mass
+}
+
+/// For every body, the index of the heavier body whose sphere of influence it's deepest in.
+/// Spheres are nested: a body's sphere reaches `d * (m / M)^(2/5)` around it, `d` being
+/// the distance to its own primary of mass `M`. The heaviest body has no primary
+pub fn dominant_primaries(bodies: &[Body]) -> Vec<Option<usize>> {
+    let mut by_mass: Vec<usize> = (0..bodies.len()).collect();
+    by_mass.sort_by(|&first, &second| bodies[second].mass.total_cmp(&bodies[first].mass));
+
+    let mut primaries = vec![None; bodies.len()];
+    let mut sphere_radii = vec![f64::INFINITY; bodies.len()];
+
+    for (rank, &index) in by_mass.iter().enumerate() {
+        let body = &bodies[index];
+
+        let primary = by_mass[..rank]
+            .iter()
+            .copied()
+            .filter(|&heavier| {
+                bodies[heavier].position.distance(body.position) < sphere_radii[heavier]
+            })
+            .min_by(|&first, &second| sphere_radii[first].total_cmp(&sphere_radii[second]));
+
+        if let Some(primary) = primary {
+            primaries[index] = Some(primary);
+            sphere_radii[index] = bodies[primary].position.distance(body.position)
+                * (body.mass / bodies[primary].mass).powf(0.4);
+        }
+    }
+
+    primaries
+}
+
+/// Below this eccentricity or sine of the inclination an orbit is treated as circular
+/// or lying in the XY plane, where the periapsis or the node isn't defined
+const DEGENERATE: f64 = 1e-11;
+
+/// Osculating Keplerian elements of a body relative to its primary.
+/// Angles are in radians between 0 and 2π, measured in the XY plane from the X axis
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Elements {
+    /// Meters, negative for hyperbolic orbits and infinite for parabolic ones
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    /// Distance from the primary at a right angle to the periapsis, in meters. `a * (1 - e²)`,
+    /// but finite for parabolic orbits too, so that they're given by these elements
+    pub semi_latus_rectum: f64,
+    /// Angle between the orbital plane and the XY plane, between 0 and π
+    pub inclination: f64,
+    /// Direction where the body passes the XY plane going up. X axis when the orbit
+    /// lies in the plane
+    pub longitude_of_ascending_node: f64,
+    /// Angle from the ascending node to the periapsis, in the direction of motion.
+    /// Zero for circular orbits
+    pub argument_of_periapsis: f64,
+    /// Angle from the periapsis to the body, in the direction of motion
+    pub true_anomaly: f64,
+}
+
+impl Elements {
+    /// Elements of a body at `relative_position` moving with `relative_velocity` relative to
+    /// its primary. `sgp` is the gravitational parameter of the pair, `G * (M + m)`
+    pub fn from_state(relative_position: DVec3, relative_velocity: DVec3, sgp: f64) -> Self {
+        let distance = relative_position.length();
+        let angular_momentum = relative_position.cross(relative_velocity);
+        let normal = angular_momentum.normalize();
+
+        let eccentricity_vector =
+            relative_velocity.cross(angular_momentum) / sgp - relative_position / distance;
+        let eccentricity = eccentricity_vector.length();
+
+        let specific_energy = relative_velocity.length_squared() / 2.0 - sgp / distance;
+
+        let inclination = normal.z.clamp(-1.0, 1.0).acos();
+
+        let node_vector = DVec3::Z.cross(angular_momentum);
+        let node_direction = if node_vector.length() > DEGENERATE * angular_momentum.length() {
+            node_vector.normalize()
+        } else {
+            DVec3::X
+        };
+        let longitude_of_ascending_node = node_direction.y.atan2(node_direction.x);
+
+        let periapsis_direction = if eccentricity > DEGENERATE {
+            eccentricity_vector / eccentricity
+        } else {
+            node_direction
+        };
+
+        Self {
+            semi_major_axis: -sgp / (2.0 * specific_energy),
+            eccentricity,
+            semi_latus_rectum: angular_momentum.length_squared() / sgp,
+            inclination,
+            longitude_of_ascending_node: longitude_of_ascending_node.rem_euclid(TAU),
+            argument_of_periapsis: angle_around(normal, node_direction, periapsis_direction),
+            true_anomaly: angle_around(normal, periapsis_direction, relative_position),
+        }
+    }
+
+    /// Position and velocity relative to the primary. `sgp` is the gravitational parameter
+    /// of the pair, `G * (M + m)`
+    pub fn to_state(&self, sgp: f64) -> (DVec3, DVec3) {
+        let eccentricity = self.eccentricity;
+        let speed = (sgp / self.semi_latus_rectum).sqrt();
+        let (sin, cos) = self.true_anomaly.sin_cos();
+
+        (
+            self.position_at(self.true_anomaly),
+            self.orientation() * DVec3::new(-speed * sin, speed * (eccentricity + cos), 0.0),
+        )
+    }
+
+    /// Position relative to the primary at `true_anomaly` along the orbit. Points of
+    /// a hyperbolic orbit exist only between the asymptotes
+    pub fn position_at(&self, true_anomaly: f64) -> DVec3 {
+        let (sin, cos) = true_anomaly.sin_cos();
+        let distance = self.semi_latus_rectum / (1.0 + self.eccentricity * cos);
+
+        self.orientation() * DVec3::new(distance * cos, distance * sin, 0.0)
+    }
+
+    /// Turns the orbital plane, with X to the periapsis and Z along the angular momentum,
+    /// into the simulation frame
+    pub fn orientation(&self) -> DMat3 {
+        DMat3::from_rotation_z(self.longitude_of_ascending_node)
+            * DMat3::from_rotation_x(self.inclination)
+            * DMat3::from_rotation_z(self.argument_of_periapsis)
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.eccentricity < 1.0
+    }
+
+    /// Center of the conic relative to the primary: of the ellipse, or where the asymptotes
+    /// of a hyperbola cross
+    pub fn center(&self) -> DVec3 {
+        self.orientation() * DVec3::new(-self.semi_major_axis * self.eccentricity, 0.0, 0.0)
+    }
+
+    /// True anomaly the body tends to when escaping: the direction of the outgoing asymptote
+    /// of a hyperbola, π for a parabola. `None` for closed orbits
+    pub fn escape_anomaly(&self) -> Option<f64> {
+        if self.is_bound() {
+            None
+        } else {
+            Some((-1.0 / self.eccentricity).acos())
+        }
+    }
+
+    /// True anomaly between 0 and π where the body is `distance` from the primary,
+    /// `None` when it never gets that close or that far
+    pub fn true_anomaly_at_distance(&self, distance: f64) -> Option<f64> {
+        let cos = (self.semi_latus_rectum / distance - 1.0) / self.eccentricity;
+
+        if (-1.0..=1.0).contains(&cos) {
+            Some(cos.acos())
+        } else {
+            None
+        }
+    }
+
+    /// Meters, imaginary for hyperbolic orbits: NaN
+    pub fn semi_minor_axis(&self) -> f64 {
+        self.semi_major_axis * (1.0 - self.eccentricity * self.eccentricity).sqrt()
+    }
+
+    /// Closest distance to the primary, in meters
+    pub fn periapsis(&self) -> f64 {
+        self.semi_latus_rectum / (1.0 + self.eccentricity)
+    }
+
+    /// Farthest distance from the primary, in meters. `None` when the body escapes
+    pub fn apoapsis(&self) -> Option<f64> {
+        if self.is_bound() {
+            Some(self.semi_major_axis * (1.0 + self.eccentricity))
+        } else {
+            None
+        }
+    }
+
+    /// Seconds per revolution, `None` when the body escapes
+    pub fn period(&self, sgp: f64) -> Option<f64> {
+        if self.is_bound() {
+            Some(TAU * (self.semi_major_axis.powi(3) / sgp).sqrt())
+        } else {
+            None
+        }
+    }
+
+    /// Angle the body would have turned since the periapsis if it moved uniformly.
+    /// Between 0 and 2π for closed orbits, any value for open ones
+    pub fn mean_anomaly(&self) -> f64 {
+        let eccentricity = self.eccentricity;
+        let half_anomaly = self.true_anomaly / 2.0;
+
+        if eccentricity < 1.0 {
+            let (sin, cos) = half_anomaly.sin_cos();
+            let eccentric_anomaly =
+                2.0 * ((1.0 - eccentricity).sqrt() * sin).atan2((1.0 + eccentricity).sqrt() * cos);
+
+            (eccentric_anomaly - eccentricity * eccentric_anomaly.sin()).rem_euclid(TAU)
+        } else if eccentricity > 1.0 {
+            let hyperbolic_anomaly = 2.0
+                * (((eccentricity - 1.0) / (eccentricity + 1.0)).sqrt() * half_anomaly.tan())
+                    .atanh();
+
+            eccentricity * hyperbolic_anomaly.sinh() - hyperbolic_anomaly
+        } else {
+            // Barker's equation
+            let tangent = half_anomaly.tan();
+
+            tangent + tangent.powi(3) / 3.0
+        }
+    }
+}
+
+/// True anomaly at `mean_anomaly`, solving Kepler's equation `M = E - e sin E`,
+/// or `M = e sinh H - H` for hyperbolic orbits. Between 0 and 2π
+pub fn true_anomaly_from_mean(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 50;
+    const TOLERANCE: f64 = 1e-15;
+
+    if eccentricity < 1.0 {
+        let mean_anomaly = mean_anomaly.rem_euclid(TAU);
+        let mut eccentric_anomaly = if eccentricity > 0.8 { PI } else { mean_anomaly };
+
+        for _ in 0..MAX_ITERATIONS {
+            let correction =
+                (eccentric_anomaly - eccentricity * eccentric_anomaly.sin() - mean_anomaly)
+                    / (1.0 - eccentricity * eccentric_anomaly.cos());
+            eccentric_anomaly -= correction;
+
+            if correction.abs() < TOLERANCE {
+                break;
+            }
+        }
+
+        let (sin, cos) = (eccentric_anomaly / 2.0).sin_cos();
+
+        (2.0 * ((1.0 + eccentricity).sqrt() * sin).atan2((1.0 - eccentricity).sqrt() * cos))
+            .rem_euclid(TAU)
+    } else {
+        let mut hyperbolic_anomaly =
+            mean_anomaly.signum() * (2.0 * mean_anomaly.abs() / eccentricity + 1.8).ln();
+
+        for _ in 0..MAX_ITERATIONS {
+            let correction =
+                (eccentricity * hyperbolic_anomaly.sinh() - hyperbolic_anomaly - mean_anomaly)
+                    / (eccentricity * hyperbolic_anomaly.cosh() - 1.0);
+            hyperbolic_anomaly -= correction;
+
+            if correction.abs() < TOLERANCE * hyperbolic_anomaly.abs().max(1.0) {
+                break;
+            }
+        }
+
+        (2.0 * (((eccentricity + 1.0) / (eccentricity - 1.0)).sqrt()
+            * (hyperbolic_anomaly / 2.0).tanh())
+        .atan())
+        .rem_euclid(TAU)
+    }
+}
+
+/// Angle from `from` to `to` turning around `axis` counterclockwise, between 0 and 2π
+fn angle_around(axis: DVec3, from: DVec3, to: DVec3) -> f64 {
+    from.cross(to).dot(axis).atan2(from.dot(to)).rem_euclid(TAU)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SGP: f64 = 1.327e20;
+
+    fn assert_close(value: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (value - expected).abs() <= tolerance * expected.abs().max(1.0),
+            "{} isn't {}",
+            value,
+            expected
+        );
+    }
+
+    fn assert_round_trip(elements: Elements) {
+        let (position, velocity) = elements.to_state(SGP);
+        let round_trip = Elements::from_state(position, velocity, SGP);
+
+        assert_close(round_trip.semi_major_axis, elements.semi_major_axis, 1e-14);
+        assert_close(round_trip.eccentricity, elements.eccentricity, 1e-14);
+        assert_close(
+            round_trip.semi_latus_rectum,
+            elements.semi_latus_rectum,
+            1e-14,
+        );
+        assert_close(round_trip.inclination, elements.inclination, 1e-14);
+        assert_close(
+            round_trip.longitude_of_ascending_node,
+            elements.longitude_of_ascending_node,
+            1e-14,
+        );
+        assert_close(
+            round_trip.argument_of_periapsis,
+            elements.argument_of_periapsis,
+            1e-14,
+        );
+        assert_close(round_trip.true_anomaly, elements.true_anomaly, 1e-14);
+    }
+
+    fn elements(semi_latus_rectum: f64, eccentricity: f64, true_anomaly: f64) -> Elements {
+        Elements {
+            semi_major_axis: semi_latus_rectum / (1.0 - eccentricity * eccentricity),
+            eccentricity,
+            semi_latus_rectum,
+            inclination: 0.5,
+            longitude_of_ascending_node: 1.2,
+            argument_of_periapsis: 2.1,
+            true_anomaly,
+        }
+    }
+
+    #[test]
+    fn elliptic_elements_round_trip() {
+        assert_round_trip(elements(1.4e11, 0.3, 0.7));
+        assert_round_trip(elements(1.4e11, 0.9, 4.0));
+    }
+
+    #[test]
+    fn hyperbolic_elements_round_trip() {
+        // the asymptotes are at ±2.30 for e = 1.5
+        assert_round_trip(elements(1.4e11, 1.5, 1.0));
+        assert_round_trip(elements(1.4e11, 3.0, 5.0));
+    }
+
+    #[test]
+    fn true_anomaly_from_mean_inverts_mean_anomaly() {
+        for eccentricity in [0.0, 0.2, 0.7, 0.99, 1.3, 5.0] {
+            for mean_anomaly in [0.1, 1.0, 3.0, 5.5] {
+                let true_anomaly = true_anomaly_from_mean(mean_anomaly, eccentricity);
+                let elements = elements(1.4e11, eccentricity, true_anomaly);
+
+                assert_close(elements.mean_anomaly(), mean_anomaly, 1e-12);
+            }
+        }
+    }
+}
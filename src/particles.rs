@@ -0,0 +1,203 @@
+use std::f64::consts::TAU;
+
+use bevy_math::DVec3;
+use bevy_tasks::TaskPool;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gravity::Gravity,
+    orbit::{self, Elements, METERS_PER_AU},
+    simulation::Body,
+};
+
+/// Massless bodies: they're pulled by the massive bodies but pull nothing, so any number
+/// of them can be integrated without slowing the massive bodies down
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TestParticles {
+    /// Meters
+    pub positions: Vec<DVec3>,
+    /// Meters per second
+    pub velocities: Vec<DVec3>,
+}
+
+impl TestParticles {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn append(&mut self, other: Self) {
+        self.positions.extend(other.positions);
+        self.velocities.extend(other.velocities);
+    }
+
+    /// Moves all the particles together, along with the massive bodies
+    pub fn shift(&mut self, position_shift: DVec3, velocity_shift: DVec3) {
+        for position in &mut self.positions {
+            *position += position_shift;
+        }
+        for velocity in &mut self.velocities {
+            *velocity += velocity_shift;
+        }
+    }
+
+    /// Advances the particles by `dt` seconds while the massive bodies moved from
+    /// `old_positions` to `new_positions`. Kick-drift-kick leapfrog whatever the integrator
+    /// of the massive bodies is, so keep the steps short enough for it.
+    /// Particles whose state stops being finite are dropped, their count is returned
+    pub fn step(
+        &mut self,
+        pool: &TaskPool,
+        gravity: &Gravity,
+        old_positions: &[DVec3],
+        new_positions: &[DVec3],
+        masses: &[f64],
+        dt: f64,
+    ) -> usize {
+        let half_dt = dt / 2.0;
+
+        let accelerations =
+            gravity.test_particle_accelerations(pool, &self.positions, old_positions, masses);
+        for ((position, velocity), acceleration) in self
+            .positions
+            .iter_mut()
+            .zip(&mut self.velocities)
+            .zip(accelerations)
+        {
+            *velocity += acceleration * half_dt;
+            *position += *velocity * dt;
+        }
+
+        let accelerations =
+            gravity.test_particle_accelerations(pool, &self.positions, new_positions, masses);
+        for (velocity, acceleration) in self.velocities.iter_mut().zip(accelerations) {
+            *velocity += acceleration * half_dt;
+        }
+
+        self.drop_diverged()
+    }
+
+    fn drop_diverged(&mut self) -> usize {
+        let count = self.len();
+        let (positions, velocities) = self
+            .positions
+            .iter()
+            .zip(&self.velocities)
+            .filter(|(position, velocity)| position.is_finite() && velocity.is_finite())
+            .unzip();
+
+        self.positions = positions;
+        self.velocities = velocities;
+
+        count - self.len()
+    }
+}
+
+/// Recipe for a set of test particles, placed on random Keplerian orbits around a body
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ParticleGenerator {
+    /// Between 2.1 and 3.3 AU around the heaviest body, somewhat eccentric and inclined
+    AsteroidBelt { count: usize },
+    /// Between 30 and 50 AU around the heaviest body
+    KuiperBelt { count: usize },
+    /// Flat ring of circular orbits between 1.2 and 2.3 radii of the body named `around`
+    Ring { around: String, count: usize },
+}
+
+/// Range of the random orbital elements of a belt
+struct Belt {
+    /// Meters
+    inner_radius: f64,
+    outer_radius: f64,
+    max_eccentricity: f64,
+    /// Radians
+    max_inclination: f64,
+}
+
+impl ParticleGenerator {
+    /// Name of the body the particles go around, the heaviest one when `None`
+    pub fn around(&self) -> Option<&str> {
+        match self {
+            Self::AsteroidBelt { .. } | Self::KuiperBelt { .. } => None,
+            Self::Ring { around, .. } => Some(around),
+        }
+    }
+
+    /// Particles around `primary`. The same `seed` gives the same particles
+    pub fn generate(&self, primary: &Body, seed: u64) -> TestParticles {
+        let (count, belt) = match self {
+            Self::AsteroidBelt { count } => (
+                *count,
+                Belt {
+                    inner_radius: 2.1 * METERS_PER_AU,
+                    outer_radius: 3.3 * METERS_PER_AU,
+                    max_eccentricity: 0.2,
+                    max_inclination: 15f64.to_radians(),
+                },
+            ),
+            Self::KuiperBelt { count } => (
+                *count,
+                Belt {
+                    inner_radius: 30.0 * METERS_PER_AU,
+                    outer_radius: 50.0 * METERS_PER_AU,
+                    max_eccentricity: 0.1,
+                    max_inclination: 10f64.to_radians(),
+                },
+            ),
+            Self::Ring { count, .. } => (
+                *count,
+                Belt {
+                    inner_radius: 1.2 * primary.diameter / 2.0,
+                    outer_radius: 2.3 * primary.diameter / 2.0,
+                    max_eccentricity: 0.0,
+                    max_inclination: 0.0,
+                },
+            ),
+        };
+
+        let mut random = Random(seed);
+        let sgp = orbit::gravitational_parameter(primary.mass, 0.0);
+        let mut particles = TestParticles::default();
+
+        for _ in 0..count {
+            let semi_major_axis =
+                belt.inner_radius + random.next() * (belt.outer_radius - belt.inner_radius);
+            let eccentricity = random.next() * belt.max_eccentricity;
+            let elements = Elements {
+                semi_major_axis,
+                eccentricity,
+                semi_latus_rectum: semi_major_axis * (1.0 - eccentricity * eccentricity),
+                inclination: random.next() * belt.max_inclination,
+                longitude_of_ascending_node: random.next() * TAU,
+                argument_of_periapsis: random.next() * TAU,
+                true_anomaly: orbit::true_anomaly_from_mean(random.next() * TAU, eccentricity),
+            };
+            let (position, velocity) = elements.to_state(sgp);
+
+            particles.positions.push(primary.position + position);
+            particles.velocities.push(primary.velocity + velocity);
+        }
+
+        particles
+    }
+}
+
+/// SplitMix64, enough to scatter particles without a dependency
+struct Random(u64);
+
+impl Random {
+    /// Uniform between 0 and 1
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,97 @@
+use std::f64::consts::{PI, TAU};
+
+use bevy_math::DVec3;
+
+const ARCSECONDS_PER_RADIAN: f64 = 180.0 * 3600.0 / PI;
+const SECONDS_PER_CENTURY: f64 = 100.0 * 365.25 * 24.0 * 3600.0;
+
+/// Measures the apsidal precession of a body: how fast the direction to its periapsis turns
+/// around the primary. The direction is given by the eccentricity vector, its angle is measured
+/// in the orbital plane from the direction at the first sample
+#[derive(Debug, Default)]
+pub struct ApsidalPrecession {
+    /// Eccentricity vector and orbital plane normal at the first sample
+    reference: Option<(DVec3, DVec3)>,
+    /// Simulated time (s) and the unwrapped periapsis angle (rad)
+    samples: Vec<(f64, f64)>,
+}
+
+impl ApsidalPrecession {
+    /// Adds a sample of the body's state relative to its primary.
+    /// `sgp` is the gravitational parameter of the orbit, `G * (M + m)` around a body
+    pub fn record(
+        &mut self,
+        time: f64,
+        relative_position: DVec3,
+        relative_velocity: DVec3,
+        sgp: f64,
+    ) {
+        let angular_momentum = relative_position.cross(relative_velocity);
+        let eccentricity_vector = relative_velocity.cross(angular_momentum) / sgp
+            - relative_position / relative_position.length();
+
+        let (reference, axis) = *self
+            .reference
+            .get_or_insert((eccentricity_vector, angular_momentum.normalize()));
+
+        let mut angle = reference
+            .cross(eccentricity_vector)
+            .dot(axis)
+            .atan2(reference.dot(eccentricity_vector));
+
+        // keeps the angle continuous when it passes ±π
+        if let Some((_, previous)) = self.samples.last() {
+            angle += ((previous - angle) / TAU).round() * TAU;
+        }
+
+        self.samples.push((time, angle));
+    }
+
+    /// Simulated time (s) and periapsis angle (rad) of every sample
+    pub fn samples(&self) -> &[(f64, f64)] {
+        &self.samples
+    }
+
+    /// Precession rate in radians per second: the least squares slope of the periapsis angle
+    pub fn rate(&self) -> Option<f64> {
+        if self.samples.len() < 2 {
+            return None;
+        }
+
+        let count = self.samples.len() as f64;
+        let (time_sum, angle_sum) = self
+            .samples
+            .iter()
+            .fold((0.0, 0.0), |(times, angles), (time, angle)| {
+                (times + time, angles + angle)
+            });
+        let (mean_time, mean_angle) = (time_sum / count, angle_sum / count);
+
+        let (covariance, variance) =
+            self.samples
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (time, angle)| {
+                    let time_offset = time - mean_time;
+
+                    (
+                        covariance + time_offset * (angle - mean_angle),
+                        variance + time_offset * time_offset,
+                    )
+                });
+
+        if variance > 0.0 {
+            Some(covariance / variance)
+        } else {
+            None
+        }
+    }
+
+    pub fn arcseconds_per_century(&self) -> Option<f64> {
+        self.rate()
+            .map(|rate| rate * ARCSECONDS_PER_RADIAN * SECONDS_PER_CENTURY)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
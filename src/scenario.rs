@@ -0,0 +1,729 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy_math::DVec3;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    gravity::{ForceSolver, Gravity},
+    horizons::HorizonsVectors,
+    integrator::Integrator,
+    maneuver::{FlightPlan, Maneuver},
+    momentum::CenterOfMass,
+    orbit::{self, Elements, OrbitPrimary},
+    particles::{ParticleGenerator, TestParticles},
+    simulation::{Body, Simulation},
+};
+
+/// The default scenario: the Sun and the eight planets
+pub const SOLAR_SYSTEM: &str = include_str!("../scenarios/solar_system.ron");
+
+/// Initial state of a simulation and the settings it's run with
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Scenario {
+    /// Screen pixels per meter at the start
+    #[serde(default)]
+    pub view_scale: Option<f32>,
+    #[serde(default)]
+    pub integrator: Integrator,
+    /// Force solver and force law
+    #[serde(default)]
+    pub gravity: Gravity,
+    /// Size of a physics step, in seconds
+    #[serde(default = "default_time_step")]
+    pub time_step: f64,
+    /// When set, all the bodies get the same extra velocity that makes the total momentum zero,
+    /// so that the system doesn't drift away. The orbits stay as they are
+    #[serde(default)]
+    pub balance_momentum: bool,
+    /// Julian date (TDB) of the Horizons states the bodies start from.
+    /// When not set, the first state of every file is taken and they must all be at the same date
+    #[serde(default)]
+    pub epoch: Option<f64>,
+    pub bodies: Vec<ScenarioBody>,
+    /// Massless particles added around the bodies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub particles: Vec<ParticleGenerator>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScenarioBody {
+    pub name: String,
+    /// Meters
+    #[serde(default)]
+    pub position: DVec3,
+    /// Meters per second
+    #[serde(default)]
+    pub velocity: DVec3,
+    /// Kilograms
+    pub mass: f64,
+    /// Meters
+    pub diameter: f64,
+    #[serde(default)]
+    pub star: bool,
+    /// Red, green and blue between 0 and 1
+    #[serde(default)]
+    pub color: Option<[f32; 3]>,
+    /// Path of a saved JPL Horizons vector table, relative to the scenario file.
+    /// The position and velocity are then read from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub horizons: Option<String>,
+    /// Keplerian orbit around another body the position and velocity are computed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orbit: Option<ScenarioOrbit>,
+    /// What the orbit of the body is drawn around
+    #[serde(default)]
+    pub draw_orbit_around: OrbitPrimary,
+    /// Burns of a spacecraft, which must be massless
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maneuvers: Vec<Maneuver>,
+}
+
+/// Keplerian elements of a body relative to its primary, angles in degrees.
+/// The orbit is computed with the gravitational parameter `G * (M + m)` of the pair
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScenarioOrbit {
+    /// Name of the primary, which must come before the body in the scenario
+    pub around: String,
+    /// Other bodies orbited together with the primary, like the second star of a binary.
+    /// The orbit is then around their barycenter, as if they were a single body
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub together_with: Vec<String>,
+    /// Meters, negative for hyperbolic orbits
+    pub semi_major_axis: f64,
+    #[serde(default)]
+    pub eccentricity: f64,
+    #[serde(default)]
+    pub inclination: f64,
+    #[serde(default)]
+    pub longitude_of_ascending_node: f64,
+    #[serde(default)]
+    pub argument_of_periapsis: f64,
+    /// At the start of the scenario
+    #[serde(default)]
+    pub mean_anomaly: f64,
+}
+
+fn default_time_step() -> f64 {
+    3600.0
+}
+
+/// Greater than zero and finite
+fn positive(value: f64) -> bool {
+    value > 0.0 && value.is_finite()
+}
+
+/// File formats a scenario can be written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Ron,
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Format given by the file extension: `.ron`, `.toml` or `.json`
+    pub fn from_path(path: &Path) -> Result<Self, ScenarioError> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        match extension.to_lowercase().as_str() {
+            "ron" => Ok(Self::Ron),
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            _ => Err(ScenarioError::UnknownFormat(extension.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    /// Extension of a file that isn't RON, TOML nor JSON
+    UnknownFormat(String),
+    /// The file doesn't follow the format, the message tells where
+    Malformed(String),
+    /// The file is well formed, but some values make no sense
+    Invalid(String),
+    /// A value couldn't be written in the format
+    Unwritable(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "can't read the scenario: {}", error),
+            Self::UnknownFormat(extension) => write!(
+                f,
+                "unknown scenario format \"{}\", expected .ron, .toml or .json",
+                extension
+            ),
+            Self::Malformed(message) => write!(f, "malformed scenario: {}", message),
+            Self::Invalid(message) => write!(f, "invalid scenario: {}", message),
+            Self::Unwritable(message) => write!(f, "can't write the scenario: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl Scenario {
+    /// The bundled solar system
+    pub fn solar_system() -> Self {
+        Self::parse(SOLAR_SYSTEM, Format::Ron).expect("the bundled scenario is valid")
+    }
+
+    /// Reads a scenario from a file, the format is given by its extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let format = Format::from_path(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        Self::parse_in(&fs::read_to_string(path)?, format, directory)
+    }
+
+    /// Reads a scenario, the Horizons files are looked for in the current directory
+    pub fn parse(text: &str, format: Format) -> Result<Self, ScenarioError> {
+        Self::parse_in(text, format, Path::new(""))
+    }
+
+    fn parse_in(text: &str, format: Format, directory: &Path) -> Result<Self, ScenarioError> {
+        let mut scenario: Self = deserialize(text, format)?;
+
+        scenario.import_horizons(directory)?;
+        scenario.place_orbits()?;
+        scenario.validate()?;
+
+        Ok(scenario)
+    }
+
+    /// Sets the state of the bodies given by Horizons files, which are then forgotten
+    fn import_horizons(&mut self, directory: &Path) -> Result<(), ScenarioError> {
+        // center and date of the first file, the others must match them
+        let mut first: Option<(String, Option<String>, f64)> = None;
+
+        for body in &mut self.bodies {
+            let file = match body.horizons.take() {
+                Some(file) => file,
+                None => continue,
+            };
+            let path = directory.join(&file);
+
+            let vectors = HorizonsVectors::load(&path)
+                .map_err(|message| ScenarioError::Malformed(format!("{}: {}", file, message)))?;
+            let state = vectors.state_at(self.epoch).ok_or_else(|| {
+                ScenarioError::Invalid(format!(
+                    "{} has no state at JD {}",
+                    file,
+                    self.epoch.unwrap_or_default()
+                ))
+            })?;
+
+            match &first {
+                None => first = Some((file, vectors.center.clone(), state.julian_date)),
+                Some((first_file, center, _)) if *center != vectors.center => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "{} is centered on {} but {} on {}",
+                        first_file,
+                        center.as_deref().unwrap_or("an unknown body"),
+                        file,
+                        vectors.center.as_deref().unwrap_or("an unknown body")
+                    )))
+                }
+                Some((first_file, _, julian_date)) if *julian_date != state.julian_date => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "{} starts at JD {} but {} at JD {}, set the epoch",
+                        first_file, julian_date, file, state.julian_date
+                    )))
+                }
+                Some(_) => {}
+            }
+
+            body.position = state.position;
+            body.velocity = state.velocity;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the state of the bodies given by orbital elements, which are then forgotten
+    fn place_orbits(&mut self) -> Result<(), ScenarioError> {
+        for index in 0..self.bodies.len() {
+            let orbit = match self.bodies[index].orbit.take() {
+                Some(orbit) => orbit,
+                None => continue,
+            };
+            let body = &self.bodies[index];
+            let invalid = |message: String| Err(ScenarioError::Invalid(message));
+
+            let mut primaries = vec![];
+            for name in std::iter::once(&orbit.around).chain(&orbit.together_with) {
+                match self.bodies[..index]
+                    .iter()
+                    .find(|primary| &primary.name == name)
+                {
+                    Some(primary) => primaries.push(primary),
+                    None => {
+                        return invalid(format!(
+                            "{} orbits {}, which isn't a body listed before it",
+                            body.name, name
+                        ))
+                    }
+                }
+            }
+            let primary = match CenterOfMass::of(
+                primaries
+                    .iter()
+                    .map(|primary| (primary.position, primary.velocity, primary.mass)),
+            ) {
+                Some(primary) => primary,
+                None => {
+                    return invalid(format!(
+                        "{} orbits bodies without a positive mass",
+                        body.name
+                    ))
+                }
+            };
+
+            let eccentricity = orbit.eccentricity;
+            let elliptic = (0.0..1.0).contains(&eccentricity) && positive(orbit.semi_major_axis);
+            let hyperbolic = eccentricity > 1.0 && positive(-orbit.semi_major_axis);
+
+            if !elliptic && !hyperbolic {
+                return invalid(format!(
+                    "{} has semi-major axis {} and eccentricity {}, expected a positive axis \
+                     with an eccentricity below 1 or a negative one with an eccentricity above 1",
+                    body.name, orbit.semi_major_axis, eccentricity
+                ));
+            }
+
+            let elements = Elements {
+                semi_major_axis: orbit.semi_major_axis,
+                eccentricity,
+                semi_latus_rectum: orbit.semi_major_axis * (1.0 - eccentricity * eccentricity),
+                inclination: orbit.inclination.to_radians(),
+                longitude_of_ascending_node: orbit.longitude_of_ascending_node.to_radians(),
+                argument_of_periapsis: orbit.argument_of_periapsis.to_radians(),
+                true_anomaly: orbit::true_anomaly_from_mean(
+                    orbit.mean_anomaly.to_radians(),
+                    eccentricity,
+                ),
+            };
+            let (position, velocity) =
+                elements.to_state(orbit::gravitational_parameter(primary.mass, body.mass));
+            let (position, velocity) = (primary.position + position, primary.velocity + velocity);
+
+            let body = &mut self.bodies[index];
+            body.position = position;
+            body.velocity = velocity;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |message: String| Err(ScenarioError::Invalid(message));
+
+        if self.bodies.iter().all(|body| body.mass == 0.0) {
+            return invalid("there are no bodies with mass".to_string());
+        }
+        if !positive(self.time_step) {
+            return invalid(format!("time step {} is not positive", self.time_step));
+        }
+        if let Some(view_scale) = self.view_scale {
+            if !positive(view_scale as f64) {
+                return invalid(format!("view scale {} is not positive", view_scale));
+            }
+        }
+        if !(self.gravity.softening >= 0.0 && self.gravity.softening.is_finite()) {
+            return invalid(format!(
+                "softening {} is not zero or positive",
+                self.gravity.softening
+            ));
+        }
+        if let Some(min_distance) = self.gravity.min_distance {
+            if !positive(min_distance) {
+                return invalid(format!("minimum distance {} is not positive", min_distance));
+            }
+        }
+        if let ForceSolver::BarnesHut { opening_angle } = self.gravity.solver {
+            if !positive(opening_angle) {
+                return invalid(format!("opening angle {} is not positive", opening_angle));
+            }
+        }
+
+        for (index, body) in self.bodies.iter().enumerate() {
+            if self.bodies[..index]
+                .iter()
+                .any(|other| other.name == body.name)
+            {
+                return invalid(format!("there are two bodies named {}", body.name));
+            }
+            if !positive(body.mass) && body.mass != 0.0 {
+                return invalid(format!(
+                    "{} has mass {}, it can't be negative",
+                    body.name, body.mass
+                ));
+            }
+            if body.mass != 0.0 && !body.maneuvers.is_empty() {
+                return invalid(format!(
+                    "{} has maneuvers, it must be massless to have them",
+                    body.name
+                ));
+            }
+            for maneuver in &body.maneuvers {
+                if !(maneuver.time >= 0.0 && maneuver.time.is_finite()) {
+                    return invalid(format!(
+                        "{} has a maneuver at {} s, before the start",
+                        body.name, maneuver.time
+                    ));
+                }
+                if !maneuver.delta_v().is_finite() {
+                    return invalid(format!(
+                        "{} has a maneuver at {} s that isn't finite",
+                        body.name, maneuver.time
+                    ));
+                }
+                if maneuver.relative_to == body.name
+                    || !self
+                        .bodies
+                        .iter()
+                        .any(|other| other.name == maneuver.relative_to)
+                {
+                    return invalid(format!(
+                        "{} has a maneuver relative to {}, which isn't another body",
+                        body.name, maneuver.relative_to
+                    ));
+                }
+            }
+            if !body.position.is_finite() || !body.velocity.is_finite() {
+                return invalid(format!(
+                    "{} has a position or velocity that isn't finite",
+                    body.name
+                ));
+            }
+            if !positive(body.diameter) && body.diameter != 0.0 {
+                return invalid(format!(
+                    "{} has diameter {}, it can't be negative",
+                    body.name, body.diameter
+                ));
+            }
+            if let Some(color) = body.color {
+                if color.iter().any(|channel| !(0.0..=1.0).contains(channel)) {
+                    return invalid(format!("{} has a color outside of 0..1", body.name));
+                }
+            }
+        }
+
+        for generator in &self.particles {
+            if let Some(name) = generator.around() {
+                match self.bodies.iter().find(|body| body.name == name) {
+                    None => {
+                        return invalid(format!("particles around {}, which isn't a body", name))
+                    }
+                    Some(body) if body.diameter == 0.0 => {
+                        return invalid(format!("a ring around {}, which has no diameter", name))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scenario starting from `bodies`, with the settings of this one and the momentum left as
+    /// it is. Whether a body is a star, its color, what its orbit is drawn around and its
+    /// maneuvers are taken from the scenario body it started as, even when it was renamed
+    /// by a merge. Particles aren't generated anymore
+    pub fn with_bodies(&self, bodies: &[Body]) -> Self {
+        let bodies = bodies
+            .iter()
+            .map(|body| {
+                let known = self
+                    .bodies
+                    .iter()
+                    .find(|known| known.name == body.scenario_name);
+
+                ScenarioBody {
+                    name: body.name.clone(),
+                    position: body.position,
+                    velocity: body.velocity,
+                    mass: body.mass,
+                    diameter: body.diameter,
+                    star: known.is_some_and(|known| known.star),
+                    color: known.and_then(|known| known.color),
+                    horizons: None,
+                    orbit: None,
+                    draw_orbit_around: known
+                        .map(|known| known.draw_orbit_around.clone())
+                        .unwrap_or_default(),
+                    maneuvers: known
+                        .map(|known| known.maneuvers.clone())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Self {
+            bodies,
+            balance_momentum: false,
+            particles: vec![],
+            ..self.clone()
+        }
+    }
+
+    /// Particles of all the generators, around the bodies as [`Self::bodies`] gives them
+    pub fn particles(&self) -> TestParticles {
+        let bodies = self.bodies();
+        let mut particles = TestParticles::default();
+
+        for (index, generator) in self.particles.iter().enumerate() {
+            let primary = match generator.around() {
+                Some(name) => bodies.iter().find(|body| body.name == name),
+                None => bodies
+                    .iter()
+                    .max_by(|first, second| first.mass.total_cmp(&second.mass)),
+            };
+
+            if let Some(primary) = primary {
+                particles.append(generator.generate(primary, index as u64));
+            }
+        }
+
+        particles
+    }
+
+    /// Maneuvers of every spacecraft that has some
+    pub fn flight_plans(&self) -> Vec<FlightPlan> {
+        self.bodies
+            .iter()
+            .filter(|body| !body.maneuvers.is_empty())
+            .map(|body| FlightPlan::new(body.name.clone(), body.maneuvers.clone()))
+            .collect()
+    }
+
+    /// Replaces the maneuvers of the spacecraft with the ones of `flight_plans`,
+    /// which know what was burned already
+    pub fn set_flight_plans(&mut self, flight_plans: &[FlightPlan]) {
+        for body in &mut self.bodies {
+            if let Some(plan) = flight_plans
+                .iter()
+                .find(|plan| plan.spacecraft == body.name)
+            {
+                body.maneuvers = plan.maneuvers.clone();
+            }
+        }
+    }
+
+    /// Headless simulation at the start of the scenario, run with its integrator and gravity
+    pub fn to_simulation(&self) -> Simulation {
+        Simulation {
+            bodies: self.bodies(),
+            gravity: self.gravity.clone(),
+            integrator: self.integrator,
+            particles: self.particles(),
+            flight_plans: self.flight_plans(),
+            ..Default::default()
+        }
+    }
+
+    /// Bodies in the order of the scenario, with the momentum balanced when asked for
+    pub fn bodies(&self) -> Vec<Body> {
+        let mut bodies: Vec<Body> = self
+            .bodies
+            .iter()
+            .map(|body| Body {
+                name: body.name.clone(),
+                scenario_name: body.name.clone(),
+                position: body.position,
+                velocity: body.velocity,
+                mass: body.mass,
+                diameter: body.diameter,
+            })
+            .collect();
+
+        if self.balance_momentum {
+            if let Some(center) = CenterOfMass::of_bodies(&bodies) {
+                for body in &mut bodies {
+                    body.velocity -= center.velocity;
+                }
+            }
+        }
+
+        bodies
+    }
+}
+
+pub(crate) fn deserialize<T: DeserializeOwned>(
+    text: &str,
+    format: Format,
+) -> Result<T, ScenarioError> {
+    match format {
+        Format::Ron => ron::from_str(text).map_err(|error| error.to_string()),
+        Format::Toml => toml::from_str(text).map_err(|error| error.to_string()),
+        Format::Json => serde_json::from_str(text).map_err(|error| error.to_string()),
+    }
+    .map_err(ScenarioError::Malformed)
+}
+
+pub(crate) fn serialize<T: Serialize>(value: &T, format: Format) -> Result<String, ScenarioError> {
+    match format {
+        Format::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string()),
+        // TOML can't hold enum variants with fields, like the Dormand–Prince settings
+        Format::Toml => Err("TOML files can only be read, use .ron or .json".to_string()),
+        Format::Json => serde_json::to_string_pretty(value).map_err(|error| error.to_string()),
+    }
+    .map_err(ScenarioError::Unwritable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::Snapshot;
+
+    const TWO_BODIES: &str = "
+        (
+            integrator: DormandPrince(tolerance: 1e-12),
+            time_step: 60.0,
+            bodies: [
+                (name: \"Sun\", mass: 1.989e30, diameter: 1.39e9),
+                (name: \"Earth\", mass: 5.972e24, diameter: 12.742e6, position: (1.496e11, 0.0, 0.0)),
+            ],
+        )";
+
+    #[test]
+    fn simulation_runs_with_the_scenario_integrator() {
+        let scenario = Scenario::parse(TWO_BODIES, Format::Ron).unwrap();
+        let simulation = scenario.to_simulation();
+
+        assert_eq!(simulation.integrator, Integrator::dormand_prince(1e-12));
+
+        let snapshot = Snapshot::of_simulation(&simulation, scenario.time_step, &scenario);
+        assert_eq!(
+            snapshot.scenario.integrator,
+            Integrator::dormand_prince(1e-12)
+        );
+    }
+
+    #[test]
+    fn bundled_scenarios_are_valid() {
+        let solar_system = Scenario::solar_system();
+        assert_eq!(solar_system.bodies.len(), 9);
+
+        for file in ["binary_star.ron", "lunar_transfer.ron", "solar_system.ron"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("scenarios")
+                .join(file);
+
+            if let Err(error) = Scenario::load(&path) {
+                panic!("{}: {}", file, error);
+            }
+        }
+    }
+
+    #[test]
+    fn the_formats_read_the_same_scenario() {
+        let toml = "
+            time_step = 60.0
+
+            [[bodies]]
+            name = \"Sun\"
+            mass = 1.989e30
+            diameter = 1.39e9
+
+            [[bodies]]
+            name = \"Earth\"
+            mass = 5.972e24
+            diameter = 12.742e6
+            position = [1.496e11, 0.0, 0.0]
+        ";
+        let json = r#"{
+            "time_step": 60.0,
+            "bodies": [
+                { "name": "Sun", "mass": 1.989e30, "diameter": 1.39e9 },
+                {
+                    "name": "Earth",
+                    "mass": 5.972e24,
+                    "diameter": 12.742e6,
+                    "position": [1.496e11, 0.0, 0.0]
+                }
+            ]
+        }"#;
+
+        let ron = Scenario::parse(TWO_BODIES, Format::Ron).unwrap();
+
+        for scenario in [
+            Scenario::parse(toml, Format::Toml).unwrap(),
+            Scenario::parse(json, Format::Json).unwrap(),
+        ] {
+            assert_eq!(scenario.time_step, ron.time_step);
+            assert_eq!(scenario.bodies.len(), 2);
+            assert_eq!(scenario.bodies[1].position, ron.bodies[1].position);
+        }
+    }
+
+    #[test]
+    fn malformed_scenarios_are_rejected() {
+        for text in [
+            "(bodies: [(name: \"Sun\", mass: 1.989e30)])",
+            "(bodies: [(name: \"Sun\", mass: 1.989e30, diameter: 1.39e9)]",
+            "(time_step: \"an hour\", bodies: [])",
+        ] {
+            assert!(
+                matches!(
+                    Scenario::parse(text, Format::Ron),
+                    Err(ScenarioError::Malformed(_))
+                ),
+                "{}",
+                text
+            );
+        }
+
+        assert!(matches!(
+            Format::from_path(Path::new("scenario.yaml")),
+            Err(ScenarioError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_scenarios_are_rejected() {
+        let sun = "(name: \"Sun\", mass: 1.989e30, diameter: 1.39e9)";
+
+        for bodies in [
+            "(name: \"Probe\", mass: 0.0, diameter: 0.0)".to_string(),
+            format!("{}, {}", sun, sun),
+            format!("{}, (name: \"Rock\", mass: -1.0, diameter: 1.0)", sun),
+            format!(
+                "{}, (name: \"Earth\", mass: 5.972e24, diameter: 12.742e6, \
+                 orbit: Some((around: \"Moon\", semi_major_axis: 1.496e11)))",
+                sun
+            ),
+        ] {
+            let text = format!("(bodies: [{}])", bodies);
+
+            assert!(
+                matches!(
+                    Scenario::parse(&text, Format::Ron),
+                    Err(ScenarioError::Invalid(_))
+                ),
+                "{}",
+                text
+            );
+        }
+
+        let text = format!("(time_step: -60.0, bodies: [{}])", sun);
+        assert!(matches!(
+            Scenario::parse(&text, Format::Ron),
+            Err(ScenarioError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,294 @@
+use bevy_math::DVec3;
+use bevy_tasks::TaskPool;
+
+use crate::{
+    collision::{self, BodyState},
+    gravity::Gravity,
+    integrator::{ForceCache, Integrator, StepReport},
+    maneuver::{self, Burn, FlightPlan},
+    particles::TestParticles,
+};
+
+#[derive(Clone, Debug)]
+pub struct Body {
+    pub name: String,
+    /// Name of the scenario body it started as. It stays when the body is renamed by a merge,
+    /// so that its scenario settings still apply
+    pub scenario_name: String,
+    /// Meters
+    pub position: DVec3,
+    /// Meters per second
+    pub velocity: DVec3,
+    /// Kilograms
+    pub mass: f64,
+    /// Meters
+    pub diameter: f64,
+}
+
+impl Body {
+    fn state(&self) -> BodyState {
+        BodyState {
+            position: self.position,
+            velocity: self.velocity,
+            mass: self.mass,
+            diameter: self.diameter,
+        }
+    }
+}
+
+/// The position or the velocity of some bodies became infinite or NaN.
+/// The step that led to it was discarded
+#[derive(Debug)]
+pub struct StateDiverged {
+    /// Names of the bodies whose state isn't finite
+    pub names: Vec<String>,
+}
+
+/// What a step did besides moving the bodies
+#[derive(Debug, Default)]
+pub struct StepOutcome {
+    pub report: StepReport,
+    /// Maneuvers burned during the step, or taken back going backwards
+    pub burns: Vec<Burn>,
+}
+
+/// Two bodies collided and were merged into the body named `"{first} + {second}"`
+#[derive(Debug)]
+pub struct Collision {
+    /// The heavier body, it took the merged state
+    pub first: String,
+    /// The lighter body, it was removed
+    pub second: String,
+    /// Index of the heavier body among the bodies that were merged
+    pub survivor: usize,
+    /// Index of the lighter body among the bodies that were merged
+    pub removed: usize,
+}
+
+impl Collision {
+    pub fn merged_name(&self) -> String {
+        format!("{} + {}", self.first, self.second)
+    }
+}
+
+/// Merges the bodies of `states` that touch each other, see [`collision::merge_collisions`].
+/// The bodies merged away keep their place in `states`, the caller removes them.
+///
+/// `names` gives the names of the bodies, indexed like `states`. It's only called when
+/// some bodies merged, the names of the collisions follow the earlier merges of the same call
+pub fn merge_bodies(
+    states: &mut [BodyState],
+    names: impl FnOnce() -> Vec<String>,
+) -> Vec<Collision> {
+    let merges = collision::merge_collisions(states);
+
+    if merges.is_empty() {
+        return vec![];
+    }
+
+    let mut names = names();
+
+    merges
+        .into_iter()
+        .map(|(survivor, removed)| {
+            let collision = Collision {
+                first: names[survivor].clone(),
+                second: names[removed].clone(),
+                survivor,
+                removed,
+            };
+            names[survivor] = collision.merged_name();
+
+            collision
+        })
+        .collect()
+}
+
+/// Everything a step moves or uses besides the bodies. [`Simulation::step`] steps its own
+/// bodies with it, frontends keeping the bodies elsewhere build it from their state,
+/// so that all of them step the same way
+pub struct Dynamics<'a> {
+    pub gravity: &'a Gravity,
+    pub integrator: &'a mut Integrator,
+    pub force_cache: &'a mut ForceCache,
+    pub particles: &'a mut TestParticles,
+    pub flight_plans: &'a mut Vec<FlightPlan>,
+    /// Simulated time the step starts at, in seconds
+    pub elapsed: f64,
+}
+
+impl Dynamics<'_> {
+    /// Integrates the bodies over `dt` seconds, backwards when `dt` is negative. `positions`,
+    /// `velocities` and `masses` are indexed the same way, so are the names `names` gives.
+    /// They're only asked for when there are maneuvers or the state diverged.
+    ///
+    /// The maneuvers met on the way are burned at their exact time. When the new state isn't
+    /// finite the maneuvers and the particles are left as they were, and the caller keeps
+    /// the old state of the bodies. Particles whose state isn't finite anymore are dropped
+    pub fn step(
+        self,
+        pool: &TaskPool,
+        dt: f64,
+        positions: &mut [DVec3],
+        velocities: &mut [DVec3],
+        masses: &[f64],
+        mut names: impl FnMut() -> Vec<String>,
+    ) -> Result<StepOutcome, StateDiverged> {
+        let old_positions = positions.to_vec();
+        // burned into only once the step is kept
+        let mut flight_plans = self.flight_plans.clone();
+        // the maneuvers find the bodies they're relative to by name
+        let bodies = if flight_plans.is_empty() {
+            vec![]
+        } else {
+            names()
+        };
+        let mut report = StepReport::default();
+
+        let gravity = self.gravity;
+        let integrator = self.integrator;
+        let mut force_cache = (!gravity.depends_on_velocities()).then_some(self.force_cache);
+        let burns = maneuver::step_with_maneuvers(
+            &mut flight_plans,
+            &bodies,
+            self.elapsed,
+            dt,
+            positions,
+            velocities,
+            |positions, velocities, dt| {
+                report.append(integrator.step(
+                    positions,
+                    velocities,
+                    dt,
+                    &|positions: &[DVec3], velocities: &[DVec3]| {
+                        gravity.accelerations(pool, positions, velocities, masses)
+                    },
+                    force_cache.as_deref_mut(),
+                ))
+            },
+        );
+
+        let diverged: Vec<usize> = positions
+            .iter()
+            .zip(velocities.iter())
+            .enumerate()
+            .filter(|(_, (position, velocity))| !position.is_finite() || !velocity.is_finite())
+            .map(|(body, _)| body)
+            .collect();
+
+        if !diverged.is_empty() {
+            let names = names();
+
+            return Err(StateDiverged {
+                names: diverged
+                    .into_iter()
+                    .map(|body| names[body].clone())
+                    .collect(),
+            });
+        }
+
+        if !self.particles.is_empty() {
+            self.particles
+                .step(pool, gravity, &old_positions, positions, masses, dt);
+        }
+
+        *self.flight_plans = flight_plans;
+
+        Ok(StepOutcome { report, burns })
+    }
+}
+
+/// N-body simulation running without any rendering
+#[derive(Debug, Default)]
+pub struct Simulation {
+    pub bodies: Vec<Body>,
+    pub gravity: Gravity,
+    pub integrator: Integrator,
+    /// Simulated time since the start, in seconds
+    pub elapsed: f64,
+    /// Moved along with the bodies, after them
+    pub particles: TestParticles,
+    /// Maneuvers of the spacecraft among the bodies
+    pub flight_plans: Vec<FlightPlan>,
+    /// Accelerations the next step starts from
+    pub force_cache: ForceCache,
+}
+
+impl Simulation {
+    pub fn new(bodies: Vec<Body>) -> Self {
+        Self {
+            bodies,
+            ..Default::default()
+        }
+    }
+
+    /// Integrates all the bodies over `dt` seconds, backwards when `dt` is negative,
+    /// see [`Dynamics::step`]. When the new state isn't finite it's discarded and the bodies
+    /// keep their old state
+    pub fn step(&mut self, pool: &TaskPool, dt: f64) -> Result<StepOutcome, StateDiverged> {
+        let mut positions: Vec<DVec3> = self.bodies.iter().map(|body| body.position).collect();
+        let mut velocities: Vec<DVec3> = self.bodies.iter().map(|body| body.velocity).collect();
+        let masses: Vec<f64> = self.bodies.iter().map(|body| body.mass).collect();
+
+        let dynamics = Dynamics {
+            gravity: &self.gravity,
+            integrator: &mut self.integrator,
+            force_cache: &mut self.force_cache,
+            particles: &mut self.particles,
+            flight_plans: &mut self.flight_plans,
+            elapsed: self.elapsed,
+        };
+        let bodies = &self.bodies;
+        let outcome = dynamics.step(pool, dt, &mut positions, &mut velocities, &masses, || {
+            bodies.iter().map(|body| body.name.clone()).collect()
+        })?;
+
+        for (body, (position, velocity)) in self
+            .bodies
+            .iter_mut()
+            .zip(positions.into_iter().zip(velocities))
+        {
+            body.position = position;
+            body.velocity = velocity;
+        }
+
+        self.elapsed += dt;
+
+        Ok(outcome)
+    }
+
+    /// Merges the bodies that touch each other, see [`merge_bodies`]
+    pub fn merge_collisions(&mut self) -> Vec<Collision> {
+        let mut states: Vec<BodyState> = self.bodies.iter().map(Body::state).collect();
+        let bodies = &self.bodies;
+        let collisions = merge_bodies(&mut states, || {
+            bodies.iter().map(|body| body.name.clone()).collect()
+        });
+
+        if collisions.is_empty() {
+            return vec![];
+        }
+
+        let mut merged_away = vec![false; self.bodies.len()];
+
+        for collision in &collisions {
+            self.bodies[collision.survivor].name = collision.merged_name();
+            merged_away[collision.removed] = true;
+        }
+
+        for (body, state) in self.bodies.iter_mut().zip(states) {
+            body.position = state.position;
+            body.velocity = state.velocity;
+            body.mass = state.mass;
+            body.diameter = state.diameter;
+        }
+
+        let mut index = 0;
+        self.bodies.retain(|_| {
+            index += 1;
+            !merged_away[index - 1]
+        });
+
+        collisions
+    }
+}
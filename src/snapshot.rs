@@ -0,0 +1,139 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    particles::TestParticles,
+    scenario::{self, Format, Scenario, ScenarioError},
+    simulation::Simulation,
+};
+
+/// Complete state of a running simulation. Loading it continues the run exactly where it was
+/// saved: the bodies are listed in the order their forces were summed up in, and the numbers
+/// are written with all their digits
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Snapshot {
+    /// Simulated time since the start, in seconds
+    pub elapsed: f64,
+    /// Simulated seconds per real second, not set by headless runs
+    #[serde(default)]
+    pub warp: Option<f64>,
+    /// The bodies as they are at `elapsed`, with the view, integrator and gravity settings
+    pub scenario: Scenario,
+    #[serde(default)]
+    pub particles: TestParticles,
+}
+
+impl Snapshot {
+    /// Snapshot of a headless simulation. Stars and colors are taken from `template`
+    pub fn of_simulation(simulation: &Simulation, time_step: f64, template: &Scenario) -> Self {
+        let mut scenario = template.with_bodies(&simulation.bodies);
+        scenario.integrator = simulation.integrator;
+        scenario.gravity = simulation.gravity.clone();
+        scenario.time_step = time_step;
+        scenario.set_flight_plans(&simulation.flight_plans);
+
+        Self {
+            elapsed: simulation.elapsed,
+            warp: None,
+            scenario,
+            particles: simulation.particles.clone(),
+        }
+    }
+
+    /// Headless simulation continuing from the snapshot
+    pub fn to_simulation(&self) -> Simulation {
+        Simulation {
+            bodies: self.scenario.bodies(),
+            gravity: self.scenario.gravity.clone(),
+            integrator: self.scenario.integrator,
+            elapsed: self.elapsed,
+            particles: self.particles.clone(),
+            flight_plans: self.scenario.flight_plans(),
+            ..Default::default()
+        }
+    }
+
+    /// Writes the snapshot to a file, the format is given by its extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ScenarioError> {
+        let path = path.as_ref();
+        let text = scenario::serialize(self, Format::from_path(path)?)?;
+
+        fs::write(path, text)?;
+
+        Ok(())
+    }
+
+    /// Reads a snapshot from a file, the format is given by its extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let snapshot: Self = scenario::deserialize(&text, Format::from_path(path)?)?;
+
+        snapshot.scenario.validate()?;
+
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use bevy_tasks::TaskPool;
+
+    use super::*;
+    use crate::integrator::Integrator;
+
+    const STEPS: usize = 100;
+
+    fn run(simulation: &mut Simulation, pool: &TaskPool, time_step: f64) {
+        for _ in 0..STEPS {
+            simulation.step(pool, time_step).unwrap();
+            simulation.merge_collisions();
+        }
+    }
+
+    #[test]
+    fn restored_runs_continue_bit_for_bit() {
+        let pool = TaskPool::new();
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/lunar_transfer.ron");
+        // the first burn comes after the snapshot
+        let mut scenario = Scenario::load(path).unwrap();
+
+        for integrator in [Integrator::Leapfrog, Integrator::dormand_prince(1e-10)] {
+            scenario.integrator = integrator;
+
+            let mut uninterrupted = scenario.to_simulation();
+            run(&mut uninterrupted, &pool, scenario.time_step);
+            let mut interrupted = scenario.to_simulation();
+            run(&mut interrupted, &pool, scenario.time_step);
+            run(&mut uninterrupted, &pool, scenario.time_step);
+            assert!(uninterrupted.flight_plans[0].used_delta_v() > 3000.0);
+
+            for extension in ["ron", "json"] {
+                let path =
+                    env::temp_dir().join(format!("snapshot-test-{}.{}", process::id(), extension));
+                Snapshot::of_simulation(&interrupted, scenario.time_step, &scenario)
+                    .save(&path)
+                    .unwrap();
+                let snapshot = Snapshot::load(&path);
+                fs::remove_file(&path).unwrap();
+
+                let mut restored = snapshot.unwrap().to_simulation();
+                run(&mut restored, &pool, scenario.time_step);
+
+                assert_eq!(restored.elapsed, uninterrupted.elapsed);
+                assert_eq!(restored.integrator, uninterrupted.integrator);
+                for (body, expected) in restored.bodies.iter().zip(&uninterrupted.bodies) {
+                    assert_eq!(body.position, expected.position, "{}", extension);
+                    assert_eq!(body.velocity, expected.velocity, "{}", extension);
+                }
+                assert_eq!(
+                    restored.flight_plans[0].used_delta_v(),
+                    uninterrupted.flight_plans[0].used_delta_v()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use bevy_math::DVec3;
+use serde::Serialize;
+
+use crate::{
+    orbit::{self, Elements},
+    simulation::Body,
+};
+
+/// Formats the trajectory can be written in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrajectoryFormat {
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl TrajectoryFormat {
+    /// Format given by the file extension: `.csv`, `.ndjson` or `.jsonl`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+/// State of a body in a sample
+#[derive(Serialize)]
+struct Row<'a> {
+    time: f64,
+    name: &'a str,
+    position: DVec3,
+    velocity: DVec3,
+    /// Missing for the primary itself and when no primary is chosen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elements: Option<Elements>,
+}
+
+/// Writes the state of every body once per `interval` of simulated time
+pub struct TrajectoryRecorder<W: Write> {
+    output: W,
+    format: TrajectoryFormat,
+    /// Simulated seconds between two samples
+    pub interval: f64,
+    /// When set, the osculating elements around the body of this name are added to every row
+    pub elements_around: Option<String>,
+    last_sample: Option<f64>,
+}
+
+impl TrajectoryRecorder<BufWriter<File>> {
+    /// Records into a new file, the format is given by its extension
+    pub fn create(path: impl AsRef<Path>, interval: f64) -> io::Result<Self> {
+        let path = path.as_ref();
+        let format = TrajectoryFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "unknown trajectory format, expected .csv, .ndjson or .jsonl",
+            )
+        })?;
+
+        Ok(Self::new(
+            BufWriter::new(File::create(path)?),
+            format,
+            interval,
+        ))
+    }
+}
+
+impl<W: Write> TrajectoryRecorder<W> {
+    pub fn new(output: W, format: TrajectoryFormat, interval: f64) -> Self {
+        Self {
+            output,
+            format,
+            interval,
+            elements_around: None,
+            last_sample: None,
+        }
+    }
+
+    /// Whether a sample is due at `time`: the first one is taken right away, the next ones
+    /// once `interval` passed, going forwards or backwards in time
+    pub fn is_due(&self, time: f64) -> bool {
+        match self.last_sample {
+            // tolerates the rounding of times summed up from fractional steps
+            Some(last_sample) => (time - last_sample).abs() >= self.interval * (1.0 - 1e-9),
+            None => true,
+        }
+    }
+
+    /// Writes a sample of `bodies` at `time` when it's due. Returns whether it was written
+    pub fn record(&mut self, time: f64, bodies: &[Body]) -> io::Result<bool> {
+        if !self.is_due(time) {
+            return Ok(false);
+        }
+
+        if self.last_sample.is_none() && self.format == TrajectoryFormat::Csv {
+            self.write_csv_header()?;
+        }
+        self.last_sample = Some(time);
+
+        let primary = self
+            .elements_around
+            .as_ref()
+            .and_then(|name| bodies.iter().find(|body| &body.name == name));
+
+        for body in bodies {
+            let elements = primary
+                .filter(|primary| primary.name != body.name)
+                .map(|primary| {
+                    Elements::from_state(
+                        body.position - primary.position,
+                        body.velocity - primary.velocity,
+                        orbit::gravitational_parameter(primary.mass, body.mass),
+                    )
+                });
+
+            let row = Row {
+                time,
+                name: &body.name,
+                position: body.position,
+                velocity: body.velocity,
+                elements,
+            };
+
+            match self.format {
+                TrajectoryFormat::Csv => self.write_csv_row(&row)?,
+                TrajectoryFormat::Ndjson => {
+                    serde_json::to_writer(&mut self.output, &row)?;
+                    writeln!(self.output)?;
+                }
+            }
+        }
+
+        self.output.flush()?;
+
+        Ok(true)
+    }
+
+    fn write_csv_header(&mut self) -> io::Result<()> {
+        write!(self.output, "time,name,x,y,z,vx,vy,vz")?;
+
+        if self.elements_around.is_some() {
+            write!(
+                self.output,
+                ",semi_major_axis,eccentricity,inclination,longitude_of_ascending_node,\
+                 argument_of_periapsis,true_anomaly"
+            )?;
+        }
+
+        writeln!(self.output)
+    }
+
+    fn write_csv_row(&mut self, row: &Row) -> io::Result<()> {
+        write!(
+            self.output,
+            "{},{},{},{},{},{},{},{}",
+            row.time,
+            csv_field(row.name),
+            row.position.x,
+            row.position.y,
+            row.position.z,
+            row.velocity.x,
+            row.velocity.y,
+            row.velocity.z,
+        )?;
+
+        if self.elements_around.is_some() {
+            match &row.elements {
+                Some(elements) => write!(
+                    self.output,
+                    ",{},{},{},{},{},{}",
+                    elements.semi_major_axis,
+                    elements.eccentricity,
+                    elements.inclination,
+                    elements.longitude_of_ascending_node,
+                    elements.argument_of_periapsis,
+                    elements.true_anomaly,
+                )?,
+                None => write!(self.output, ",,,,,,")?,
+            }
+        }
+
+        writeln!(self.output)
+    }
+}
+
+/// Quotes names containing commas or quotes, like "Earth + Moon, merged"
+fn csv_field(text: &str) -> String {
+    if text.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
@@ -1,4 +1,5 @@
 use bevy::prelude::*;
+use planetary_simulation_rs::gravity::Gravity;
 
 pub mod toggle_switch;
 
@@ -7,13 +8,17 @@ pub struct UiPlugin;
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Materials>()
+            .init_resource::<DeltaVBudget>()
             .add_plugin(toggle_switch::ToggleSwitchPlugin)
-            .add_startup_system(draw.system());
+            .add_startup_system(draw.system())
+            .add_system(update_delta_v_bar.system());
     }
 }
 
 struct Materials {
     bg: Handle<ColorMaterial>,
+    bar_bg: Handle<ColorMaterial>,
+    delta_v_bar: Handle<ColorMaterial>,
     toggle_switch: toggle_switch::Materials,
 }
 
@@ -22,6 +27,8 @@ impl FromWorld for Materials {
         let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
         Materials {
             bg: materials.add(Color::rgba(0.1, 0.1, 0.1, 0.5).into()),
+            bar_bg: materials.add(Color::BLACK.into()),
+            delta_v_bar: materials.add(Color::ORANGE.into()),
             toggle_switch: toggle_switch::Materials::from_world(world),
         }
     }
@@ -33,7 +40,29 @@ pub struct AddSunToggle;
 #[derive(Component, Clone)]
 pub struct ShowTracesToggle;
 
-fn draw(mut commands: Commands, ui_materials: Res<Materials>) {
+#[derive(Component, Clone)]
+pub struct PostNewtonianToggle;
+
+/// Delta-v of the maneuvers of all the spacecraft, in meters per second.
+/// The part used so far is shown as a bar and as numbers under it
+#[derive(Debug, Default, PartialEq)]
+pub struct DeltaVBudget {
+    pub used: f64,
+    pub planned: f64,
+}
+
+#[derive(Component)]
+struct DeltaVBar;
+
+#[derive(Component)]
+struct DeltaVText;
+
+fn draw(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    ui_materials: Res<Materials>,
+    gravity: Res<Gravity>,
+) {
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -53,5 +82,85 @@ fn draw(mut commands: Commands, ui_materials: Res<Materials>) {
             ShowTracesToggle,
             toggle_switch::ToggleState(false),
             &ui_materials.toggle_switch,
-        ));
+        ))
+        .with_children(toggle_switch::draw(
+            PostNewtonianToggle,
+            toggle_switch::ToggleState(gravity.post_newtonian),
+            &ui_materials.toggle_switch,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(180.0), Val::Px(8.0)),
+                        margin: Rect::all(Val::Px(4.0)),
+                        ..Default::default()
+                    },
+                    material: ui_materials.bar_bg.clone(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                                ..Default::default()
+                            },
+                            material: ui_materials.delta_v_bar.clone(),
+                            ..Default::default()
+                        })
+                        .insert(DeltaVBar);
+                });
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    style: Style {
+                        margin: Rect::all(Val::Px(4.0)),
+                        ..Default::default()
+                    },
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load("fonts/DejaVuSansMono.ttf"),
+                            font_size: 14.0,
+                            color: Color::WHITE,
+                        },
+                        TextAlignment::default(),
+                    ),
+                    ..Default::default()
+                })
+                .insert(DeltaVText);
+        });
+}
+
+fn update_delta_v_bar(
+    budget: Res<DeltaVBudget>,
+    mut bars: Query<&mut Style, With<DeltaVBar>>,
+    mut texts: Query<&mut Text, With<DeltaVText>>,
+) {
+    if !budget.is_changed() {
+        return;
+    }
+
+    let used = if budget.planned > 0.0 {
+        (budget.used / budget.planned).min(1.0)
+    } else {
+        0.0
+    };
+
+    for mut style in bars.iter_mut() {
+        style.size.width = Val::Percent(used as f32 * 100.0);
+    }
+
+    // nothing to show without spacecraft
+    let text = if budget.planned > 0.0 {
+        format!("{:.0} / {:.0} m/s", budget.used, budget.planned)
+    } else {
+        String::new()
+    };
+
+    for mut value in texts.iter_mut() {
+        value.sections[0].value = text.clone();
+    }
 }